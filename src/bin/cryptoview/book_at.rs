@@ -0,0 +1,123 @@
+/// The `book-at` subcommand, which reconstructs a product's book at a point
+/// in time and prints its ladder.
+
+use std::io;
+use std::io::Write;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::Future;
+use serde_json;
+
+use cryptoview::Timestamp;
+use cryptoview::historical::feed::{book_at, Reconstruction, ReplayGap};
+
+use super::ladder;
+use super::{data_store, is_number, is_time, product, time_of, EXIT_FAILURE, EXIT_OK};
+
+pub fn describe_gap(gap: &ReplayGap) -> String {
+    match *gap {
+        ReplayGap::MissingChunk(hour) => format!("missing the chunk for {}", hour),
+        ReplayGap::Skipped(last, seq) => format!("skipped from {} to {}", last, seq),
+    }
+}
+
+fn write_text<W: Write>(r: &Reconstruction, time: Timestamp, levels: usize, mut w: W) -> io::Result<()> {
+    writeln!(
+        w,
+        "{} at {}: {} from {} {}, then {} events up to sequence {}",
+        r.product,
+        time,
+        if r.from_checkpoint { "checkpoint" } else { "snapshot" },
+        r.snapshot.time,
+        r.snapshot.path.display(),
+        r.events_applied,
+        r.seq
+    )?;
+    for gap in &r.gaps {
+        writeln!(w, "Gap: {}", describe_gap(gap))?;
+    }
+    if !r.valid {
+        writeln!(
+            w,
+            "The book is probably wrong, since there was no snapshot after the last gap"
+        )?;
+    }
+    writeln!(w)?;
+    ladder::write_text(&r.book, levels, w)
+}
+
+fn write_json<W: Write>(r: &Reconstruction, time: Timestamp, levels: usize, mut w: W) -> io::Result<()> {
+    let mut obj = ladder::to_json(&r.book, levels);
+    obj["product"] = json!(r.product);
+    obj["time"] = json!(time.to_string());
+    obj["sequence"] = json!(u64::from(r.seq));
+    obj["snapshot"] = json!(r.snapshot.path.display().to_string());
+    obj["from_checkpoint"] = json!(r.from_checkpoint);
+    obj["events_applied"] = json!(r.events_applied);
+    obj["gaps"] = json!(r.gaps.iter().map(describe_gap).collect::<Vec<_>>());
+    obj["valid"] = json!(r.valid);
+    serde_json::to_writer_pretty(&mut w, &obj)?;
+    writeln!(w)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("book-at")
+        .about("Prints the ladder of a product's book at a point in time")
+        .after_help(
+            "Loads the latest snapshot or checkpoint of the product at or before the time,\n\
+             and replays the messages which follow it, up to and including the time.\n\
+             Needs --data-dir and one --product.\n\n\
+             CSV has only the ladder. Text and JSON also say how the book was built, and\n\
+             whether it's probably wrong because of a gap in the messages.",
+        )
+        .arg(
+            Arg::with_name("levels")
+                .long("levels")
+                .short("n")
+                .value_name("LEVELS")
+                .default_value("10")
+                .validator(is_number)
+                .help("How many levels of each side to print"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .possible_values(&["text", "json", "csv"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::with_name("TIME")
+                .required(true)
+                .validator(is_time)
+                .help("An RFC 3339 time, e.g. 2018-02-25T17:42:10Z"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let (store, product) = (data_store(matches), product(matches));
+    let time = time_of(matches, "TIME");
+    let levels = value_t_or_exit!(matches, "levels", usize);
+    let r = match book_at(&store, &product, time).wait() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let time = Timestamp::from(time);
+    let stdout = io::stdout();
+    let w = stdout.lock();
+    let result = match matches.value_of("format") {
+        Some("json") => write_json(&r, time, levels, w),
+        Some("csv") => ladder::write_csv(&r.book, levels, w),
+        _ => write_text(&r, time, levels, w),
+    };
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
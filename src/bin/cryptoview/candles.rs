@@ -0,0 +1,119 @@
+/// The `candles` subcommand, which builds candles from the matches recorded
+/// over a period.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::io::{BufWriter, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::Stream;
+
+use cryptoview::Timestamp;
+use cryptoview::book::{Level3Event, Level3FeedListener};
+use cryptoview::candles::{write_csv, write_csv_header, Bars, Candles, EmptyIntervals};
+use cryptoview::coinbase::Message;
+use cryptoview::decode::LineDecoder;
+use cryptoview::historical::feed::{messages_between, FeedItem};
+
+use super::{data_store, is_time, products, time_of, usage_error, EXIT_FAILURE, EXIT_OK};
+
+fn is_bars(s: String) -> Result<(), String> {
+    s.parse::<Bars>().map(|_| ())
+}
+
+fn write_candles<W: Write>(matches: &ArgMatches, mut w: W) -> io::Result<()> {
+    let store = data_store(matches);
+    let products = products(matches);
+    if products.is_empty() {
+        usage_error("at least one --product is required");
+    }
+    let bars = value_t_or_exit!(matches, "bars", Bars);
+    let empty = value_t_or_exit!(matches, "empty", EmptyIntervals);
+    let (start, end) = (time_of(matches, "START"), time_of(matches, "END"));
+
+    let mut candles: BTreeMap<String, Candles> = products
+        .iter()
+        .map(|p| (p.clone(), Candles::new(bars).with_empty_intervals(empty)))
+        .collect();
+    let products: HashSet<String> = products.into_iter().collect();
+    let mut decoder = LineDecoder::new(store.error_policy());
+    write_csv_header(&mut w)?;
+    for item in messages_between(&store, start, end, products)?.wait() {
+        let (line, source) = match item? {
+            FeedItem::Message(line, source) => (line, source),
+            FeedItem::MissingChunk(hour) => {
+                eprintln!("Missing the chunk for {}", hour);
+                continue;
+            }
+            FeedItem::Unreadable(error, source) => {
+                let e = io::Error::new(io::ErrorKind::InvalidData, error);
+                decoder.read_error_at(&source.chunk, source.line, e)?;
+                continue;
+            }
+        };
+        let message = decoder.decode_at(&source.chunk, source.line, &line, Message::parse)?;
+        let message = match message {
+            Some(message) => message,
+            None => continue,
+        };
+        let event = match decoder.check(message.to_event())? {
+            Some(Some(Level3Event::Match(event))) => event,
+            _ => continue,
+        };
+        let product = message.product_id.as_ref().map(|p| p.as_str()).unwrap_or("");
+        if let Some(candles) = candles.get_mut(product) {
+            candles.on_match(&event);
+            write_csv(product, &candles.take(), &mut w)?;
+        }
+    }
+
+    // Bars which end by the end time are complete, even without later trades.
+    let end = Timestamp::from(end);
+    for (product, candles) in &mut candles {
+        candles.close_until(end);
+        candles.finish();
+        write_csv(product, &candles.take(), &mut w)?;
+    }
+    if decoder.skipped() > 0 {
+        eprintln!("Skipped {} messages", decoder.skipped());
+    }
+    w.flush()
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("candles")
+        .about("Writes candles built from the recorded matches of products, as CSV")
+        .after_help(
+            "Bars are either time intervals like 1s, 1m, 5m or 1h, aligned to the epoch,\n\
+             or volume:<size> or ticks:<trades>. The last bar of each product may be\n\
+             partial. Needs --data-dir and at least one --product.",
+        )
+        .arg(
+            Arg::with_name("bars")
+                .long("bars")
+                .value_name("BARS")
+                .default_value("1m")
+                .validator(is_bars)
+                .help("When each bar closes"),
+        )
+        .arg(
+            Arg::with_name("empty")
+                .long("empty")
+                .possible_values(&["skip", "carry"])
+                .default_value("skip")
+                .help("Whether to leave out time intervals without trades, or carry the close"),
+        )
+        .arg(Arg::with_name("START").required(true).validator(is_time))
+        .arg(Arg::with_name("END").required(true).validator(is_time))
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let stdout = io::stdout();
+    match write_candles(matches, BufWriter::new(stdout.lock())) {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
@@ -0,0 +1,102 @@
+/// The `catalog` subcommand, which lists what's in the data dir.
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use cryptoview::catalog::{Catalog, Manifest, ProductCoverage, MANIFEST_NAME};
+
+use super::{data_store, products, EXIT_FAILURE, EXIT_OK};
+
+/// Reads the manifest at `path`, or starts a new one if there isn't a usable
+/// one there.
+fn read_manifest(path: &Path) -> Manifest {
+    match File::open(path).and_then(Manifest::read) {
+        Ok(manifest) => manifest,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Manifest::new(),
+        Err(e) => {
+            eprintln!("Ignoring {}: {}", path.display(), e);
+            Manifest::new()
+        }
+    }
+}
+
+fn print_product(coverage: &ProductCoverage) {
+    print!("{}: {} hours", coverage.product, coverage.hours.len());
+    if let Some(range) = coverage.sequences() {
+        print!(
+            ", sequences {} to {} ({} messages)",
+            range.first, range.last, range.messages
+        );
+    }
+    println!(", {} snapshots", coverage.snapshots.len());
+
+    if let (Some(first), Some(last)) = (coverage.hours.keys().next(), coverage.hours.keys().last()) {
+        println!("  hours {} to {}", first, last);
+    }
+    for hour in &coverage.missing_hours {
+        println!("  missing hour {}", hour);
+    }
+    for snapshot in &coverage.snapshots {
+        match snapshot.sequence {
+            Some(seq) => println!("  snapshot {} at sequence {}", snapshot.time, seq),
+            None => println!("  snapshot {} unreadable", snapshot.time),
+        }
+    }
+}
+
+fn print_catalog(catalog: &Catalog, products: &[String]) {
+    match (catalog.first_hour, catalog.last_hour) {
+        (Some(first), Some(last)) => println!("Chunks from {} to {}", first, last),
+        _ => println!("No chunks"),
+    }
+    for hour in &catalog.missing_chunks {
+        println!("Missing chunk {}", hour);
+    }
+    for name in &catalog.unreadable {
+        println!("Unreadable {}", name);
+    }
+    for coverage in catalog.products.values() {
+        if products.is_empty() || products.contains(&coverage.product) {
+            print_product(coverage);
+        }
+    }
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("catalog")
+        .about("Lists the products in the data dir, their coverage and missing files")
+        .after_help("The scan results are cached in manifest.json in the data dir.")
+        .arg(
+            Arg::with_name("rescan")
+                .long("rescan")
+                .help("Scans every file again, rather than only new ones"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let store = data_store(matches);
+    let path = store.root().join(MANIFEST_NAME);
+    let mut manifest = if matches.is_present("rescan") {
+        Manifest::new()
+    } else {
+        read_manifest(&path)
+    };
+    let result = manifest.update(&store).and_then(|scanned| {
+        manifest.write(BufWriter::new(File::create(&path)?))?;
+        Ok(scanned)
+    });
+    match result {
+        Ok(scanned) => eprintln!("Scanned {} new files", scanned),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+    }
+
+    print_catalog(&manifest.catalog(), &products(matches));
+    EXIT_OK
+}
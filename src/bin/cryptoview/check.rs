@@ -0,0 +1,620 @@
+/// The `check` subcommand, which checks the sequence numbers of recorded
+/// websocket messages for gaps and other problems.
+
+use rayon::prelude::*;
+use std::cmp;
+use std::io;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufReader, BufWriter, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde_json;
+
+use cryptoview::compression;
+use cryptoview::decode::{decode_lines, ErrorPolicy};
+use cryptoview::historical::storage::Glob;
+use cryptoview::sequence::{Sequence, SequenceCounts, SequenceEvent, SequenceTracker};
+
+use super::{data_store, error_policy, is_number, products, EXIT_FAILURE, EXIT_OK,
+            EXIT_PROBLEMS, EXIT_USAGE};
+
+#[derive(Deserialize, Debug)]
+struct Message {
+    sequence: Option<u64>,
+    product_id: Option<String>,
+    time: Option<String>,
+}
+
+/// A discontinuity in a product's sequence numbers within a file.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Issue {
+    Skipped {
+        product: String,
+        line: u64,
+        last: Sequence,
+        seq: Sequence,
+    },
+    Duplicate {
+        product: String,
+        line: u64,
+        seq: Sequence,
+    },
+    OutOfOrder {
+        product: String,
+        line: u64,
+        last: Sequence,
+        seq: Sequence,
+    },
+    Reordered {
+        product: String,
+        line: u64,
+        seq: Sequence,
+    },
+}
+
+impl Issue {
+    fn describe(&self) -> String {
+        match *self {
+            Issue::Skipped {
+                ref product,
+                last,
+                seq,
+                ..
+            } => format!(
+                "Skipped sequence numbers between {} and {} on product {}",
+                last, seq, product
+            ),
+            Issue::Duplicate {
+                ref product, seq, ..
+            } => format!("Duplicate sequence number {} on product {}", seq, product),
+            Issue::OutOfOrder {
+                ref product,
+                last,
+                seq,
+                ..
+            } => format!(
+                "Sequence number {} out of order after {} on product {}",
+                seq, last, product
+            ),
+            Issue::Reordered {
+                ref product, seq, ..
+            } => format!(
+                "Sequence number {} arrived late, filling a gap on product {}",
+                seq, product
+            ),
+        }
+    }
+
+    fn line(&self) -> u64 {
+        match *self {
+            Issue::Skipped { line, .. }
+            | Issue::Duplicate { line, .. }
+            | Issue::OutOfOrder { line, .. }
+            | Issue::Reordered { line, .. } => line,
+        }
+    }
+}
+
+/// The sequence numbers and times of a product's messages within a file.
+#[derive(Serialize, Debug)]
+struct ProductRange {
+    first: Sequence,
+    last: Sequence,
+    first_time: Option<String>,
+    last_time: Option<String>,
+    messages: u64,
+    skipped: u64,
+    missing: u64,
+    duplicate: u64,
+    out_of_order: u64,
+    reordered: u64,
+}
+
+impl ProductRange {
+    fn new(seq: Sequence, time: &Option<String>) -> ProductRange {
+        ProductRange {
+            first: seq,
+            last: seq,
+            first_time: time.clone(),
+            last_time: time.clone(),
+            messages: 0,
+            skipped: 0,
+            missing: 0,
+            duplicate: 0,
+            out_of_order: 0,
+            reordered: 0,
+        }
+    }
+
+    fn update(&mut self, seq: Sequence, time: &Option<String>) {
+        if seq > self.last {
+            self.last = seq;
+            if time.is_some() {
+                self.last_time = time.clone();
+            }
+        }
+    }
+
+    fn set_counts(&mut self, counts: SequenceCounts) {
+        self.messages = counts.messages;
+        self.skipped = counts.skipped;
+        self.missing = counts.missing;
+        self.duplicate = counts.duplicate;
+        self.out_of_order = counts.out_of_order;
+        self.reordered = counts.reordered;
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ParseError {
+    line: u64,
+    error: String,
+}
+
+/// What was found in one file.
+#[derive(Serialize, Debug)]
+struct FileReport {
+    file: String,
+    /// Why the file couldn't be read to the end, if it couldn't.
+    error: Option<String>,
+    products: BTreeMap<String, ProductRange>,
+    issues: Vec<Issue>,
+    /// Lines which couldn't be read or parsed, and were skipped.
+    skipped_lines: u64,
+    /// Their errors, unless only counting them.
+    parse_errors: Vec<ParseError>,
+}
+
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum BoundaryKind {
+    /// Sequence numbers are missing between the files.
+    Gap,
+    /// The later file starts with sequence numbers already seen, e.g.
+    /// because the files were recorded at the same time.
+    Overlap,
+}
+
+/// A product which didn't carry on from one file where it left off in an
+/// earlier one.
+#[derive(Serialize, Debug)]
+struct Boundary {
+    kind: BoundaryKind,
+    product: String,
+    from_file: String,
+    from_time: Option<String>,
+    from_seq: Sequence,
+    to_file: String,
+    to_time: Option<String>,
+    to_seq: Sequence,
+    /// The sequence numbers missing, or seen again.
+    count: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct Report {
+    files: Vec<FileReport>,
+    boundaries: Vec<Boundary>,
+}
+
+impl Report {
+    fn exit_code(&self) -> i32 {
+        let failed = self.files
+            .iter()
+            .any(|f| f.error.is_some() || !f.parse_errors.is_empty());
+        let gaps = !self.boundaries.is_empty() || self.files.iter().any(|f| {
+            f.products
+                .values()
+                .any(|p| p.missing > 0 || p.duplicate > 0 || p.out_of_order > 0)
+        });
+        if failed {
+            EXIT_FAILURE
+        } else if gaps {
+            EXIT_PROBLEMS
+        } else {
+            EXIT_OK
+        }
+    }
+}
+
+struct SeqChecker {
+    products: BTreeMap<String, SequenceTracker>,
+    reorder_window: u64,
+}
+
+impl SeqChecker {
+    fn new(reorder_window: u64) -> SeqChecker {
+        SeqChecker {
+            products: BTreeMap::new(),
+            reorder_window,
+        }
+    }
+
+    fn update(&mut self, product: &str, seq: Sequence, line: u64) -> Option<Issue> {
+        let window = self.reorder_window;
+        let tracker = self.products
+            .entry(product.to_owned())
+            .or_insert_with(|| SequenceTracker::with_reorder_window(window));
+        let product = product.to_owned();
+        match tracker.update(seq) {
+            SequenceEvent::First | SequenceEvent::Contiguous => None,
+            SequenceEvent::Skipped(last, seq) => Some(Issue::Skipped {
+                product,
+                line,
+                last,
+                seq,
+            }),
+            SequenceEvent::Duplicate(seq) => Some(Issue::Duplicate { product, line, seq }),
+            SequenceEvent::OutOfOrder(last, seq) => Some(Issue::OutOfOrder {
+                product,
+                line,
+                last,
+                seq,
+            }),
+            SequenceEvent::Reordered(seq) => Some(Issue::Reordered { product, line, seq }),
+        }
+    }
+}
+
+fn parse_message(line: &str) -> io::Result<Message> {
+    serde_json::from_str(line)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Bad message: {}", e)))
+}
+
+fn read_file(
+    filename: &str,
+    options: &Options,
+    checker: &mut SeqChecker,
+    report: &mut FileReport,
+) -> io::Result<()> {
+    let b = BufReader::new(compression::open(filename)?);
+    let mut lines = decode_lines(b, options.errors, parse_message);
+    for result in lines.by_ref() {
+        let (line_number, m) = result?;
+        // Subscription acknowledgements and the like have no sequence.
+        let (product, seq, time) = match (m.product_id, m.sequence) {
+            (Some(product), Some(seq)) => (product, Sequence::from(seq), m.time),
+            _ => continue,
+        };
+        if !options.products.is_empty() && !options.products.contains(&product) {
+            continue;
+        }
+        if let Some(issue) = checker.update(&product, seq, line_number) {
+            report.issues.push(issue);
+        }
+        report
+            .products
+            .entry(product)
+            .or_insert_with(|| ProductRange::new(seq, &time))
+            .update(seq, &time);
+    }
+    report.skipped_lines = lines.decoder().skipped();
+    report.parse_errors = lines
+        .decoder()
+        .errors()
+        .iter()
+        .map(|e| ParseError {
+            line: e.line,
+            error: e.message.clone(),
+        })
+        .collect();
+    Ok(())
+}
+
+/// How to check each file.
+struct Options {
+    reorder_window: u64,
+    errors: ErrorPolicy,
+    /// The products to check, or all of them if empty.
+    products: HashSet<String>,
+}
+
+fn process_file(filename: &String, options: &Options) -> FileReport {
+    let mut report = FileReport {
+        file: filename.clone(),
+        error: None,
+        products: BTreeMap::new(),
+        issues: Vec::new(),
+        skipped_lines: 0,
+        parse_errors: Vec::new(),
+    };
+    let mut checker = SeqChecker::new(options.reorder_window);
+    if let Err(e) = read_file(filename, options, &mut checker, &mut report) {
+        report.error = Some(e.to_string());
+    }
+    for (product, tracker) in &checker.products {
+        if let Some(range) = report.products.get_mut(product) {
+            range.set_counts(tracker.counts());
+        }
+    }
+    report
+}
+
+/// Where a product was last seen, in the files checked so far.
+struct LastSeen<'a> {
+    file: &'a str,
+    seq: Sequence,
+    time: &'a Option<String>,
+}
+
+/// Checks that each product carries on from file to file where it left off.
+/// A product may be missing from some files, in which case it's compared with
+/// the last file it was in.
+fn check_boundaries(files: &[FileReport]) -> Vec<Boundary> {
+    let mut boundaries = Vec::new();
+    let mut last_seen = BTreeMap::<&str, LastSeen>::new();
+    for file in files {
+        for (product, range) in &file.products {
+            if let Some(prev) = last_seen.get(&**product) {
+                if !prev.seq.is_followed_by(range.first) {
+                    let (kind, count) = if range.first > prev.seq {
+                        (BoundaryKind::Gap, prev.seq.gap(range.first))
+                    } else {
+                        let last = cmp::min(prev.seq, range.last);
+                        (BoundaryKind::Overlap, (last - range.first + 1) as u64)
+                    };
+                    boundaries.push(Boundary {
+                        kind,
+                        product: product.clone(),
+                        from_file: prev.file.to_owned(),
+                        from_time: prev.time.clone(),
+                        from_seq: prev.seq,
+                        to_file: file.file.clone(),
+                        to_time: range.first_time.clone(),
+                        to_seq: range.first,
+                        count,
+                    });
+                }
+            }
+            last_seen.insert(
+                product,
+                LastSeen {
+                    file: &file.file,
+                    seq: range.last,
+                    time: &range.last_time,
+                },
+            );
+        }
+    }
+    boundaries
+}
+
+fn write_text<W: Write>(report: &Report, mut w: W) -> io::Result<()> {
+    let time = |t: &Option<String>| t.clone().unwrap_or_else(|| "unknown time".to_owned());
+    for file in &report.files {
+        for issue in &file.issues {
+            writeln!(w, "{}:{}: {}", file.file, issue.line(), issue.describe())?;
+        }
+        for e in &file.parse_errors {
+            writeln!(w, "{}:{}: Bad message: {}", file.file, e.line, e.error)?;
+        }
+        if file.skipped_lines > 0 {
+            writeln!(w, "{}: Skipped {} lines", file.file, file.skipped_lines)?;
+        }
+        if let Some(ref e) = file.error {
+            writeln!(w, "{}: Error: {}", file.file, e)?;
+        }
+        let products: Vec<&String> = file.products.keys().collect();
+        writeln!(w, "Finished checking {}. Products: {:?}", file.file, products)?;
+        for (product, range) in &file.products {
+            writeln!(
+                w,
+                "{}: {}: {} messages, {} skips ({} missing), {} duplicates, {} out of order, {} reordered",
+                file.file,
+                product,
+                range.messages,
+                range.skipped,
+                range.missing,
+                range.duplicate,
+                range.out_of_order,
+                range.reordered
+            )?;
+        }
+    }
+    for boundary in &report.boundaries {
+        let (kind, count) = match boundary.kind {
+            BoundaryKind::Gap => ("Gap", "missing"),
+            BoundaryKind::Overlap => ("Overlap", "seen again"),
+        };
+        writeln!(
+            w,
+            "{} detected on {} between files {} ({}, seq {}) and {} ({}, seq {}): {} {}",
+            kind,
+            boundary.product,
+            boundary.from_file,
+            time(&boundary.from_time),
+            boundary.from_seq,
+            boundary.to_file,
+            time(&boundary.to_time),
+            boundary.to_seq,
+            boundary.count,
+            count
+        )?;
+    }
+    Ok(())
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("check")
+        .about("Checks the sequence numbers of recorded websocket messages")
+        .after_help(
+            "Checks the given files, or every chunk in the data dir. Lines which can't be\n\
+             parsed are collected by default.\n\n\
+             Exits with 0 if the files are clean, 1 if there are gaps or other problems\n\
+             with their sequence numbers, and 3 if a file can't be read or has lines\n\
+             which can't be parsed, unless they're skipped.",
+        )
+        .arg(
+            Arg::with_name("reorder-window")
+                .long("reorder-window")
+                .value_name("SEQUENCE NUMBERS")
+                .default_value("0")
+                .validator(is_number)
+                .help("How far back a late message may fill a gap, rather than be out of order"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("How to print the report"),
+        )
+        .arg(
+            Arg::with_name("FILE")
+                .multiple(true)
+                .help("Chunks of websocket messages, optionally compressed"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let options = Options {
+        reorder_window: value_t_or_exit!(matches, "reorder-window", u64),
+        errors: error_policy(matches, ErrorPolicy::Collect),
+        products: products(matches).into_iter().collect(),
+    };
+    let mut files: Vec<String> = match matches.values_of("FILE") {
+        Some(files) => files.map(|f| f.to_owned()).collect(),
+        None => {
+            let store = data_store(matches);
+            let chunks = match store.storage().glob(&store.chunk_glob()) {
+                Ok(paths) => paths
+                    .filter(|p| p.as_ref().map_or(true, |p| store.parse_chunk_path(p).is_some()))
+                    .map(|p| p.map(|p| p.display().to_string()).map_err(|e| e.to_string()))
+                    .collect(),
+                Err(e) => Err(e.to_string()),
+            };
+            match chunks {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+    };
+    if files.is_empty() {
+        eprintln!("No files to check");
+        return EXIT_USAGE;
+    }
+    files.sort();
+
+    let files: Vec<FileReport> = files
+        .par_iter()
+        .map(|f| process_file(f, &options))
+        .collect();
+    let boundaries = check_boundaries(&files);
+    let report = Report { files, boundaries };
+
+    let stdout = io::stdout();
+    let mut w = BufWriter::new(stdout.lock());
+    let written = if matches.value_of("format") == Some("json") {
+        serde_json::to_writer_pretty(&mut w, &report)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            .and_then(|()| writeln!(w))
+    } else {
+        write_text(&report, &mut w)
+    };
+    if let Err(e) = written.and_then(|()| w.flush()) {
+        eprintln!("Error: {}", e);
+        return EXIT_FAILURE;
+    }
+    report.exit_code()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The report of a file with each product's first and last sequence
+    /// numbers.
+    fn file(name: &str, products: &[(&str, u64, u64)]) -> FileReport {
+        let mut report = FileReport {
+            file: name.to_owned(),
+            error: None,
+            products: BTreeMap::new(),
+            issues: Vec::new(),
+            skipped_lines: 0,
+            parse_errors: Vec::new(),
+        };
+        for &(product, first, last) in products {
+            let mut range = ProductRange::new(Sequence::from(first), &None);
+            range.update(Sequence::from(last), &None);
+            report.products.insert(product.to_owned(), range);
+        }
+        report
+    }
+
+    type Summary = (BoundaryKind, String, String, String, u64);
+
+    fn boundaries_of(files: &[FileReport]) -> Vec<Summary> {
+        check_boundaries(files)
+            .into_iter()
+            .map(|b| (b.kind, b.product, b.from_file, b.to_file, b.count))
+            .collect()
+    }
+
+    fn summary(kind: BoundaryKind, product: &str, from: &str, to: &str, count: u64) -> Summary {
+        (kind, product.to_owned(), from.to_owned(), to.to_owned(), count)
+    }
+
+    #[test]
+    fn boundaries() {
+        let files = vec![
+            file("a", &[("BTC-USD", 1, 5), ("ETH-USD", 1, 3)]),
+            file("b", &[("BTC-USD", 6, 8)]),
+            // ETH-USD carries on from where it left off in a.
+            file("c", &[("BTC-USD", 9, 9), ("ETH-USD", 4, 5)]),
+            file("d", &[("BTC-USD", 11, 12)]),
+            file("e", &[("ETH-USD", 7, 7)]),
+            // Recorded alongside d and e.
+            file("f", &[("BTC-USD", 10, 14), ("ETH-USD", 7, 7)]),
+        ];
+        use self::BoundaryKind::{Gap, Overlap};
+        assert_eq!(
+            vec![
+                summary(Gap, "BTC-USD", "c", "d", 1),
+                summary(Gap, "ETH-USD", "c", "e", 1),
+                summary(Overlap, "BTC-USD", "d", "f", 3),
+                summary(Overlap, "ETH-USD", "e", "f", 1),
+            ],
+            boundaries_of(&files)
+        );
+        assert!(boundaries_of(&files[..3]).is_empty());
+    }
+
+    #[test]
+    fn exit_codes() {
+        let report = |files: Vec<FileReport>| {
+            let boundaries = check_boundaries(&files);
+            Report { files, boundaries }
+        };
+        let clean = || vec![file("a", &[("BTC-USD", 1, 5)]), file("b", &[("BTC-USD", 6, 8)])];
+        assert_eq!(EXIT_OK, report(clean()).exit_code());
+
+        let mut files = clean();
+        files[1].products.get_mut("BTC-USD").unwrap().first = Sequence::from(7);
+        assert_eq!(EXIT_PROBLEMS, report(files).exit_code());
+
+        let mut files = clean();
+        files[0].products.get_mut("BTC-USD").unwrap().missing = 1;
+        assert_eq!(EXIT_PROBLEMS, report(files).exit_code());
+
+        // Lines skipped without keeping their errors aren't a failure.
+        let mut files = clean();
+        files[0].skipped_lines = 1;
+        assert_eq!(EXIT_OK, report(files).exit_code());
+
+        let mut files = clean();
+        files[1].products.get_mut("BTC-USD").unwrap().duplicate = 1;
+        files[0].parse_errors.push(ParseError {
+            line: 3,
+            error: "Bad message".to_owned(),
+        });
+        assert_eq!(EXIT_FAILURE, report(files).exit_code());
+
+        let mut files = clean();
+        files[1].error = Some("unexpected end of file".to_owned());
+        assert_eq!(EXIT_FAILURE, report(files).exit_code());
+    }
+}
@@ -0,0 +1,75 @@
+/// The `check-snapshots` subcommand, which checks whether replay can start
+/// from each snapshot.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::Future;
+
+use cryptoview::historical::feed::{snapshot_coverage, SnapshotCoverage};
+
+use super::{data_store, is_time, time_of, EXIT_FAILURE, EXIT_OK, EXIT_PROBLEMS};
+
+/// Prints how far replay can go from a snapshot. Returns whether it can
+/// start from it at all.
+fn print_coverage(c: &SnapshotCoverage) -> bool {
+    let name = c.snapshot.path.display();
+    let seq = match c.sequence {
+        Some(seq) => seq,
+        None => {
+            println!("{}: unreadable", name);
+            return false;
+        }
+    };
+    if c.too_old {
+        println!("{}: sequence {}, too old to check", name, seq);
+        return false;
+    }
+    match (c.contiguous_until, c.next) {
+        (Some((last, time)), _) => {
+            println!(
+                "{}: sequence {}, usable, contiguous up to {} at {}",
+                name, seq, last, time
+            );
+            true
+        }
+        (None, Some(next)) => {
+            println!("{}: sequence {}, unusable, next recorded is {}", name, seq, next);
+            false
+        }
+        (None, None) => {
+            println!("{}: sequence {}, unusable, nothing recorded after it", name, seq);
+            false
+        }
+    }
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("check-snapshots")
+        .about("Checks whether the recorded messages carry on from each snapshot")
+        .after_help(
+            "Checks the latest snapshot of each product at or before the start time, and\n\
+             every snapshot after it up to the end time. Exits with 1 if replay can't\n\
+             start from some of them.",
+        )
+        .arg(Arg::with_name("START").required(true).validator(is_time))
+        .arg(Arg::with_name("END").required(true).validator(is_time))
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let store = data_store(matches);
+    let (start, end) = (time_of(matches, "START"), time_of(matches, "END"));
+    let coverage = match snapshot_coverage(&store, start, end).wait() {
+        Ok(coverage) => coverage,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let usable = coverage.iter().filter(|c| print_coverage(c)).count();
+    println!("{} of {} snapshots usable", usable, coverage.len());
+    if usable < coverage.len() {
+        EXIT_PROBLEMS
+    } else {
+        EXIT_OK
+    }
+}
@@ -0,0 +1,53 @@
+/// The `checkpoints` subcommand, which saves the books of every product at
+/// regular intervals.
+
+use chrono::Duration;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::Stream;
+
+use cryptoview::historical::feed::{checkpoints, save_checkpoint};
+
+use super::{data_store, is_time, time_of, usage_error, EXIT_FAILURE, EXIT_OK};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("checkpoints")
+        .about("Saves a checkpoint of each product's book at regular intervals")
+        .after_help(
+            "Replays every product with a snapshot at or before the start time, and saves\n\
+             its book in the data dir at each multiple of the interval up to the end time.\n\
+             Books are left out between a gap and the next snapshot after it.",
+        )
+        .arg(Arg::with_name("START").required(true).validator(is_time))
+        .arg(Arg::with_name("END").required(true).validator(is_time))
+        .arg(
+            Arg::with_name("SECONDS")
+                .required(true)
+                .help("The interval between checkpoints"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let store = data_store(matches);
+    let (start, end) = (time_of(matches, "START"), time_of(matches, "END"));
+    let interval = match value_t!(matches, "SECONDS", i64) {
+        Ok(secs) if secs > 0 => Duration::seconds(secs),
+        _ => usage_error("the interval must be a positive number of seconds"),
+    };
+
+    let mut count = 0;
+    for checkpoint in checkpoints(&store, start, end, interval).wait() {
+        let result = checkpoint.and_then(|c| save_checkpoint(&store, &c).map(|_| c));
+        match result {
+            Ok(c) => {
+                println!("{} {}", c.product, c.time);
+                count += 1;
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return EXIT_FAILURE;
+            }
+        }
+    }
+    println!("Wrote {} checkpoints", count);
+    EXIT_OK
+}
@@ -0,0 +1,86 @@
+/// The `convert` subcommand, which converts websocket messages to an event
+/// log.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use cryptoview::coinbase::Message;
+use cryptoview::compression;
+use cryptoview::decode;
+use cryptoview::decode::{ErrorPolicy, LineDecoder};
+use cryptoview::eventlog::EventLogWriter;
+
+use super::{error_policy, EXIT_FAILURE, EXIT_OK};
+
+/// Converts the level 3 events in a chunk of websocket messages to an event
+/// log, returning the number of events written. Other messages are dropped,
+/// and those which don't decode are handled by `decoder`'s policy.
+fn convert(input: &str, output: &str, decoder: &mut LineDecoder) -> io::Result<u64> {
+    let r = BufReader::new(compression::open(input)?);
+    let mut writer = EventLogWriter::new(BufWriter::new(File::create(output)?))?;
+    let mut count = 0;
+    for line in decode::lines(r) {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                decoder.read_error(e)?;
+                continue;
+            }
+        };
+        let m = match decoder.decode(&line, Message::parse)? {
+            Some(m) => m,
+            None => continue,
+        };
+        let event = match decoder.check(m.to_event())? {
+            Some(Some(event)) => event,
+            _ => continue,
+        };
+        let product = m.product_id
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Message is missing product_id"));
+        let product = match decoder.check(product)? {
+            Some(product) => product,
+            None => continue,
+        };
+        writer.write_event(product, &event)?;
+        count += 1;
+    }
+    writer.into_inner()?;
+    Ok(count)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("convert")
+        .about("Converts a chunk of websocket messages to a level 3 event log")
+        .arg(
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("The chunk, e.g. ws_20180225_170000.txt.gz"),
+        )
+        .arg(Arg::with_name("OUTPUT").required(true).help("The event log to write"))
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let (input, output) = (
+        matches.value_of("INPUT").unwrap(),
+        matches.value_of("OUTPUT").unwrap(),
+    );
+    let mut decoder = LineDecoder::new(error_policy(matches, ErrorPolicy::Abort));
+    match convert(input, output, &mut decoder) {
+        Ok(count) => println!("Wrote {} events to {}", count, output),
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            return EXIT_FAILURE;
+        }
+    }
+    for e in decoder.errors() {
+        eprintln!("{}: {}", input, e);
+    }
+    if decoder.skipped() > 0 {
+        eprintln!("{}: Skipped {} lines", input, decoder.skipped());
+    }
+    EXIT_OK
+}
@@ -0,0 +1,88 @@
+/// The `index` subcommand, which builds sidecar indexes of chunks.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use cryptoview::compression;
+use cryptoview::compression::Compression;
+use cryptoview::index;
+
+use super::{is_number, EXIT_FAILURE, EXIT_OK};
+
+/// Builds the index of the chunk at `path` and writes it next to the chunk.
+/// Returns the number of checkpoints.
+fn index_chunk(path: &str, interval: u64, recompress: bool) -> io::Result<usize> {
+    if recompress {
+        recompress_chunk(path, interval)?;
+    }
+    let index = index::build(path, File::open(path)?, interval)?;
+    index.write(BufWriter::new(File::create(index::index_path(path))?))?;
+    Ok(index.checkpoints.len())
+}
+
+/// Rewrites the chunk at `path` as members or frames of about `member_size`
+/// decompressed bytes each. Uncompressed chunks are left as they are.
+fn recompress_chunk(path: &str, member_size: u64) -> io::Result<()> {
+    let compression = compression::detect(path, &mut BufReader::new(File::open(path)?))?;
+    if compression == Compression::None {
+        return Ok(());
+    }
+    let tmp = format!("{}.tmp", path);
+    index::recompress(
+        compression,
+        compression::open(path)?,
+        BufWriter::new(File::create(&tmp)?),
+        member_size,
+    )?;
+    fs::rename(&tmp, path)
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("index")
+        .about("Builds the index of each chunk, for seeking within it")
+        .after_help(
+            "Each index is written next to its chunk, with .idx appended to the name.\n\
+             Recorders usually compress a chunk as a single gzip member or zstd frame,\n\
+             which has to be decompressed from its start even when seeking. Indexing\n\
+             with --recompress first splits such chunks into a member or frame per\n\
+             interval, so that seeking skips most of the decompression too.",
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .value_name("BYTES")
+                .validator(is_number)
+                .help("Decompressed bytes between checkpoints [default: 4 MiB]"),
+        )
+        .arg(
+            Arg::with_name("recompress")
+                .long("recompress")
+                .help("Rewrite compressed chunks as a member or frame per interval first"),
+        )
+        .arg(
+            Arg::with_name("CHUNK")
+                .required(true)
+                .multiple(true)
+                .help("Chunks of websocket messages, optionally compressed"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let interval = value_t!(matches, "interval", u64).unwrap_or(index::DEFAULT_INTERVAL);
+    let recompress = matches.is_present("recompress");
+    let mut code = EXIT_OK;
+    for path in matches.values_of("CHUNK").unwrap() {
+        match index_chunk(path, interval, recompress) {
+            Ok(count) => println!("{}: {} checkpoints", path, count),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                code = EXIT_FAILURE;
+            }
+        }
+    }
+    code
+}
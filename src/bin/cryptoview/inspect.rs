@@ -0,0 +1,146 @@
+/// The `inspect` subcommand, which summarizes book snapshots.
+
+use std::io;
+use std::io::{BufWriter, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use cryptoview::book::{Book, Side};
+use cryptoview::coinbase::BookSnapshot;
+use cryptoview::compression;
+use cryptoview::Timestamp;
+
+use super::ladder;
+use super::{is_number, EXIT_FAILURE, EXIT_OK};
+
+const DEFAULT_DEPTHS: &[u32] = &[10, 50, 100];
+
+fn is_bps(s: String) -> Result<(), String> {
+    s.parse::<u32>()
+        .map(|_| ())
+        .map_err(|_| format!("bad basis points {:?}", s))
+}
+
+fn print_summary(book: &Book, depths: &[u32]) {
+    for &(name, side) in &[("Bids", Side::Bid), ("Asks", Side::Ask)] {
+        println!(
+            "{}: {} levels, {} orders",
+            name,
+            book.levels(side).len(),
+            book.order_count(side)
+        );
+    }
+    match book.best_bid() {
+        Some(bid) => println!("Best bid: {} x {}", bid.price, bid.size),
+        None => println!("Best bid: none"),
+    }
+    match book.best_ask() {
+        Some(ask) => println!("Best ask: {} x {}", ask.price, ask.size),
+        None => println!("Best ask: none"),
+    }
+    if let (Some(spread), Some(mid)) = (book.spread(), book.mid()) {
+        println!(
+            "Spread: {} ({:.2} bps of mid {})",
+            spread,
+            f64::from(spread) / f64::from(mid) * 10000.,
+            mid
+        );
+    }
+    for &bps in depths {
+        if let (Some(bid), Some(ask)) = (book.depth(Side::Bid, bps), book.depth(Side::Ask, bps)) {
+            println!("Depth within {} bps: {} bid, {} ask", bps, bid, ask);
+        }
+    }
+}
+
+/// Writes each order of the snapshot as `side,price,size,order_id`.
+fn write_csv<W: Write>(snapshot: &BookSnapshot, mut w: W) -> io::Result<()> {
+    writeln!(w, "side,price,size,order_id")?;
+    for &(side, entries) in &[("bid", &snapshot.bids), ("ask", &snapshot.asks)] {
+        for entry in entries {
+            writeln!(w, "{},{}", side, entry.join(","))?;
+        }
+    }
+    w.flush()
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("inspect")
+        .about("Summarizes a level 3 book snapshot")
+        .after_help(
+            "The summary has the depth within 10, 50 and 100 basis points of the mid,\n\
+             unless other depths are given.",
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .value_name("LEVELS")
+                .validator(is_number)
+                .help("Also prints a ladder of the top levels of each side"),
+        )
+        .arg(
+            Arg::with_name("depth")
+                .long("depth")
+                .value_name("BPS")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(is_bps)
+                .help("Basis points from the mid to sum the depth within"),
+        )
+        .arg(
+            Arg::with_name("csv")
+                .long("csv")
+                .conflicts_with_all(&["top", "depth"])
+                .help("Converts the snapshot to CSV instead, one order per line"),
+        )
+        .arg(
+            Arg::with_name("SNAPSHOT")
+                .required(true)
+                .help("The snapshot file, optionally compressed"),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let filename = matches.value_of("SNAPSHOT").unwrap();
+    let depths = if matches.is_present("depth") {
+        values_t_or_exit!(matches, "depth", u32)
+    } else {
+        DEFAULT_DEPTHS.to_vec()
+    };
+
+    let snapshot = match compression::open(filename).and_then(BookSnapshot::read) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            return EXIT_FAILURE;
+        }
+    };
+    if matches.is_present("csv") {
+        let stdout = io::stdout();
+        if let Err(e) = write_csv(&snapshot, BufWriter::new(stdout.lock())) {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+        return EXIT_OK;
+    }
+
+    // The time isn't part of the snapshot, and nothing here depends on it.
+    let book = match snapshot.to_book(Timestamp::from_nanos(0)) {
+        Ok(book) => book,
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            return EXIT_FAILURE;
+        }
+    };
+    println!("Sequence: {}", snapshot.sequence);
+    print_summary(&book, &depths);
+    if let Ok(levels) = value_t!(matches, "top", usize) {
+        println!();
+        let stdout = io::stdout();
+        if let Err(e) = ladder::write_text(&book, levels, stdout.lock()) {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+    }
+    EXIT_OK
+}
@@ -0,0 +1,86 @@
+/// Price ladders of the top levels of a book, as text, JSON or CSV.
+
+use std::cmp;
+use std::io;
+use std::io::Write;
+
+use serde_json;
+
+use cryptoview::book::{Book, LadderLevel, Side};
+
+const HEADER: [&str; 5] = ["side", "price", "size", "orders", "cumulative"];
+
+/// The rows of the ladder, asks above bids so that the best prices are in
+/// the middle.
+fn rows(book: &Book, levels: usize) -> Vec<[String; 5]> {
+    let row = |side: &str, level: &LadderLevel| {
+        [
+            side.to_owned(),
+            level.price.to_string(),
+            level.size.to_string(),
+            level.orders.to_string(),
+            level.cumulative.to_string(),
+        ]
+    };
+    let mut rows = Vec::new();
+    for level in book.ladder(Side::Ask, levels).iter().rev() {
+        rows.push(row("ask", level));
+    }
+    for level in &book.ladder(Side::Bid, levels) {
+        rows.push(row("bid", level));
+    }
+    rows
+}
+
+/// Writes up to `levels` levels of each side as right-aligned columns.
+pub fn write_text<W: Write>(book: &Book, levels: usize, mut w: W) -> io::Result<()> {
+    let rows = rows(book, levels);
+    let mut widths = [0; 5];
+    for (i, header) in HEADER.iter().enumerate() {
+        widths[i] = rows.iter().map(|row| row[i].len()).fold(header.len(), cmp::max);
+    }
+    let mut write_row = |row: &[&str]| -> io::Result<()> {
+        let cells: Vec<String> = row.iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:>1$}", cell, width))
+            .collect();
+        writeln!(w, "{}", cells.join("  "))
+    };
+    write_row(&HEADER)?;
+    for row in &rows {
+        let row: Vec<&str> = row.iter().map(|cell| cell.as_str()).collect();
+        write_row(&row)?;
+    }
+    Ok(())
+}
+
+/// Writes up to `levels` levels of each side as CSV, with a header row.
+pub fn write_csv<W: Write>(book: &Book, levels: usize, mut w: W) -> io::Result<()> {
+    writeln!(w, "{}", HEADER.join(","))?;
+    for row in rows(book, levels) {
+        writeln!(w, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
+/// Up to `levels` levels of each side, from the inside out, as JSON. Prices
+/// and sizes are strings, as in the Coinbase feed.
+pub fn to_json(book: &Book, levels: usize) -> serde_json::Value {
+    let side = |side| -> Vec<serde_json::Value> {
+        book.ladder(side, levels)
+            .iter()
+            .map(|level| {
+                json!({
+                    "price": level.price.to_string(),
+                    "size": level.size.to_string(),
+                    "orders": level.orders,
+                    "cumulative": level.cumulative.to_string(),
+                })
+            })
+            .collect()
+    };
+    json!({
+        "bids": side(Side::Bid),
+        "asks": side(Side::Ask),
+    })
+}
@@ -0,0 +1,184 @@
+extern crate chrono;
+#[macro_use]
+extern crate clap;
+extern crate cryptoview;
+extern crate futures;
+extern crate rayon;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+
+use std::process;
+
+use chrono::{DateTime, Utc};
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use cryptoview::decode::ErrorPolicy;
+use cryptoview::historical::feed::DataStore;
+use cryptoview::Timestamp;
+
+mod book_at;
+mod candles;
+mod catalog;
+mod check;
+mod check_snapshots;
+mod checkpoints;
+mod convert;
+mod index;
+mod inspect;
+mod ladder;
+mod replay;
+
+/// Exit codes of every subcommand, for automation.
+pub const EXIT_OK: i32 = 0;
+/// The data has problems the subcommand looks for, like gaps.
+pub const EXIT_PROBLEMS: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+/// Something went wrong, e.g. a file couldn't be read.
+pub const EXIT_FAILURE: i32 = 3;
+
+/// The options of the data dir, of the products to look at within it, and of
+/// what to do with what can't be decoded.
+fn data_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("data-dir")
+            .long("data-dir")
+            .short("d")
+            .value_name("DIR")
+            .env("CRYPTOVIEW_DATA_DIR")
+            .hide_env_values(true)
+            .global(true)
+            .help("Where the recorded chunks and snapshots are"),
+        Arg::with_name("product")
+            .long("product")
+            .short("p")
+            .value_name("PRODUCT")
+            .multiple(true)
+            .number_of_values(1)
+            .global(true)
+            .help("A product to look at, e.g. BTC-USD. May be given more than once"),
+        Arg::with_name("errors")
+            .long("errors")
+            .value_name("POLICY")
+            .possible_values(&["skip", "collect", "abort"])
+            .global(true)
+            .help("What to do with messages which can't be decoded. By default check collects them, and the rest abort"),
+    ]
+}
+
+/// The error policy given, or else `default`.
+pub fn error_policy(matches: &ArgMatches, default: ErrorPolicy) -> ErrorPolicy {
+    if matches.is_present("errors") {
+        value_t_or_exit!(matches, "errors", ErrorPolicy)
+    } else {
+        default
+    }
+}
+
+/// The store in the data dir, or a usage error if there isn't one.
+pub fn data_store(matches: &ArgMatches) -> DataStore {
+    let store = match matches.value_of("data-dir") {
+        Some(dir) => DataStore::new(dir),
+        None => usage_error("--data-dir is required"),
+    };
+    store.with_error_policy(error_policy(matches, ErrorPolicy::Abort))
+}
+
+/// The products given, which may be none.
+pub fn products(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .values_of("product")
+        .map_or_else(Vec::new, |values| values.map(|p| p.to_owned()).collect())
+}
+
+/// The one product given, or a usage error.
+pub fn product(matches: &ArgMatches) -> String {
+    let mut products = products(matches);
+    if products.len() != 1 {
+        usage_error("exactly one --product is required");
+    }
+    products.remove(0)
+}
+
+/// Validates an RFC 3339 time, like 2018-02-25T17:00:00Z, which must be
+/// within the range of a `Timestamp`.
+pub fn is_time(s: String) -> Result<(), String> {
+    let time = DateTime::parse_from_rfc3339(&s)
+        .map_err(|_| format!("bad time {:?}, expected e.g. 2018-02-25T17:00:00Z", s))?;
+    match Timestamp::from_datetime(time.with_timezone(&Utc)) {
+        Some(_) => Ok(()),
+        None => Err(format!("time {:?} is out of range, e.g. before 1970", s)),
+    }
+}
+
+/// The value of an argument validated with `is_time`, in UTC.
+pub fn time_of(matches: &ArgMatches, name: &str) -> DateTime<Utc> {
+    let time = matches.value_of(name).expect("missing required time");
+    DateTime::parse_from_rfc3339(time)
+        .expect("time wasn't validated")
+        .with_timezone(&Utc)
+}
+
+/// Validates a non-negative integer.
+pub fn is_number(s: String) -> Result<(), String> {
+    s.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("bad number {:?}", s))
+}
+
+pub fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(EXIT_USAGE);
+}
+
+fn main() {
+    let app = App::new("cryptoview")
+        .about("Inspects, checks and replays recorded Coinbase level 3 feeds")
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .after_help(
+            "Exits with 0 on success, 1 if the data has problems the subcommand looks for,\n\
+             2 if the command line is wrong, and 3 if something else goes wrong.",
+        )
+        .args(&data_args())
+        .subcommand(inspect::subcommand())
+        .subcommand(check::subcommand())
+        .subcommand(check_snapshots::subcommand())
+        .subcommand(book_at::subcommand())
+        .subcommand(catalog::subcommand())
+        .subcommand(convert::subcommand())
+        .subcommand(replay::subcommand())
+        .subcommand(candles::subcommand())
+        .subcommand(index::subcommand())
+        .subcommand(checkpoints::subcommand());
+    let matches = match app.get_matches_safe() {
+        Ok(matches) => matches,
+        Err(e) => {
+            if !e.use_stderr() {
+                // Help or version.
+                println!("{}", e.message);
+                process::exit(EXIT_OK);
+            }
+            eprintln!("{}", e.message);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    let code = match matches.subcommand() {
+        ("inspect", Some(m)) => inspect::run(m),
+        ("check", Some(m)) => check::run(m),
+        ("check-snapshots", Some(m)) => check_snapshots::run(m),
+        ("book-at", Some(m)) => book_at::run(m),
+        ("catalog", Some(m)) => catalog::run(m),
+        ("convert", Some(m)) => convert::run(m),
+        ("replay", Some(m)) => replay::run(m),
+        ("candles", Some(m)) => candles::run(m),
+        ("index", Some(m)) => index::run(m),
+        ("checkpoints", Some(m)) => checkpoints::run(m),
+        _ => unreachable!("a subcommand is required"),
+    };
+    process::exit(code);
+}
@@ -0,0 +1,90 @@
+/// The `replay` subcommand, which replays products' messages over a period,
+/// printing each change to the inside of the book.
+
+use std::collections::HashSet;
+use std::io;
+
+use chrono::Duration;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::{Future, Stream};
+
+use cryptoview::book::{BestLevel, Level1Event, Level1EventListener};
+use cryptoview::historical::feed::{messages_between, replay_from, DataStore, ReplayEvent};
+
+use super::book_at::describe_gap;
+use super::{data_store, is_time, products, time_of, usage_error, EXIT_FAILURE, EXIT_OK};
+
+struct InsidePrinter {
+    product: String,
+}
+
+impl Level1EventListener for InsidePrinter {
+    fn on_level1_change(&mut self, event: &Level1Event) {
+        let level = |level: Option<BestLevel>| match level {
+            Some(level) => format!("{} x {}", level.price, level.size),
+            None => "none".to_owned(),
+        };
+        println!(
+            "{} {} {} bid {} ask {}",
+            event.time(),
+            self.product,
+            event.seq(),
+            level(event.best_bid()),
+            level(event.best_ask())
+        );
+    }
+}
+
+fn replay(store: &DataStore, product: &str, matches: &ArgMatches) -> io::Result<()> {
+    let (start, end) = (time_of(matches, "START"), time_of(matches, "END"));
+    let mut r = replay_from(store, product, start, end).wait()?;
+    r.book.set_level1_listener(Box::new(InsidePrinter {
+        product: product.to_owned(),
+    }));
+
+    let mut products = HashSet::new();
+    products.insert(product.to_owned());
+    let messages = messages_between(store, start + Duration::nanoseconds(1), end, products)?;
+    for item in messages.wait() {
+        for event in r.apply(item?)? {
+            match event {
+                ReplayEvent::Gap(gap) => eprintln!("{}: Gap: {}", product, describe_gap(&gap)),
+                ReplayEvent::Resync(resync) => {
+                    eprintln!(
+                        "{}: Rebuilt from snapshot {} at sequence {}",
+                        product,
+                        resync.snapshot.path.display(),
+                        resync.seq
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("replay")
+        .about("Replays products' messages, printing each change to the inside of the book")
+        .after_help(
+            "Starts from the book at the start time, as with book-at. Each product given\n\
+             is replayed in turn.",
+        )
+        .arg(Arg::with_name("START").required(true).validator(is_time))
+        .arg(Arg::with_name("END").required(true).validator(is_time))
+}
+
+pub fn run(matches: &ArgMatches) -> i32 {
+    let store = data_store(matches);
+    let products = products(matches);
+    if products.is_empty() {
+        usage_error("at least one --product is required");
+    }
+    for product in &products {
+        if let Err(e) = replay(&store, product, matches) {
+            eprintln!("{}: Error: {}", product, e);
+            return EXIT_FAILURE;
+        }
+    }
+    EXIT_OK
+}
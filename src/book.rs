@@ -3,16 +3,27 @@
 /// Implementation of a level 3 book.
 
 use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 use std::rc::Rc;
 use price::Price;
+pub use sequence::Sequence;
+pub use timestamp::Timestamp;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Side {
     Bid,
     Ask,
 }
 
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+}
+
 pub struct PriceLevel {
     orders: VecDeque<Rc<RefCell<Order>>>,
     open_size: Price,
@@ -30,6 +41,15 @@ impl PriceLevel {
         self.open_size
     }
 
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// The orders resting at this level, in time priority.
+    pub fn orders(&self) -> Vec<Ref<Order>> {
+        self.orders.iter().map(|o| o.borrow()).collect()
+    }
+
     fn on_open(&mut self, ord: Rc<RefCell<Order>>) {
         let size = ord.borrow().open_size;
         assert!(size >= Price::zero());
@@ -48,10 +68,13 @@ impl PriceLevel {
         assert!(self.open_size >= Price::zero());
     }
 
-    fn on_done(&mut self, size: Price) {
+    fn on_done(&mut self, ord: &Rc<RefCell<Order>>, size: Price) {
         assert!(size >= Price::zero());
         self.open_size -= size;
         assert!(self.open_size >= Price::zero());
+        if let Some(pos) = self.orders.iter().position(|o| Rc::ptr_eq(o, ord)) {
+            self.orders.remove(pos);
+        }
     }
 }
 
@@ -66,6 +89,54 @@ pub trait Level2EventListener {
     );
 }
 
+/// The best price on one side of the book, and the total open size at that price.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BestLevel {
+    pub price: Price,
+    pub size: Price,
+}
+
+/// A limit level as seen on a ladder, with the total open size from the
+/// inside up to and including it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LadderLevel {
+    pub price: Price,
+    pub size: Price,
+    pub orders: usize,
+    pub cumulative: Price,
+}
+
+/// A change to the inside of the book (best bid or ask price or size).
+pub struct Level1Event {
+    seq: Sequence,
+    time: Timestamp,
+    bid: Option<BestLevel>,
+    ask: Option<BestLevel>,
+}
+
+impl Level1Event {
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn best_bid(&self) -> Option<BestLevel> {
+        self.bid
+    }
+
+    pub fn best_ask(&self) -> Option<BestLevel> {
+        self.ask
+    }
+}
+
+pub trait Level1EventListener {
+    /// Called after any event that changes the best bid or ask price or size.
+    fn on_level1_change(&mut self, event: &Level1Event);
+}
+
 type OrdersByPrice = BTreeMap<OrderPrice, PriceLevel>;
 
 pub struct Order {
@@ -74,6 +145,8 @@ pub struct Order {
     price: OrderPrice,
     orig_size: Price,
     open_size: Price,
+    /// Whether the order has been opened, i.e. is resting on the book.
+    resting: bool,
 }
 
 impl Order {
@@ -84,12 +157,30 @@ impl Order {
             price,
             orig_size: size,
             open_size: size,
+            resting: false,
         }
     }
 
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn open_size(&self) -> Price {
+        self.open_size
+    }
+
     fn on_open(&mut self, remaining_size: Price) {
         assert!(remaining_size >= Price::zero());
         self.open_size = remaining_size;
+        self.resting = true;
     }
 
     fn on_match_maker(&mut self, size: Price) {
@@ -105,7 +196,9 @@ impl Order {
     }
 
     fn on_done(&mut self, reason: DoneReason) -> Price {
-        if reason == DoneReason::Filled {
+        // Fills of taker orders aren't tracked, so only resting orders can be
+        // checked.
+        if reason == DoneReason::Filled && self.resting {
             assert_eq!(Price::zero(), self.open_size);
         }
         self.open_size
@@ -120,17 +213,11 @@ impl<'a> From<&'a NewOrderEvent<'a>> for Order {
             price: o.price,
             orig_size: o.orig_size,
             open_size: o.open_size,
+            resting: false,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Timestamp(u64);
-
-/// The sequence number of the source event.
-#[derive(Copy, Clone, Debug)]
-pub struct Sequence(u64);
-
 /// The type and price of the order.
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum OrderPrice {
@@ -146,6 +233,7 @@ pub enum DoneReason {
 
 // Event structs. These include the data that is common to all feeds.
 
+#[derive(Debug, PartialEq)]
 pub struct NewOrderEvent<'a> {
     seq: Sequence,
     time: Timestamp,
@@ -156,6 +244,52 @@ pub struct NewOrderEvent<'a> {
     open_size: Price,
 }
 
+impl<'a> NewOrderEvent<'a> {
+    pub fn new(
+        seq: Sequence,
+        time: Timestamp,
+        order_id: &'a str,
+        side: Side,
+        price: OrderPrice,
+        size: Price,
+    ) -> NewOrderEvent<'a> {
+        NewOrderEvent {
+            seq,
+            time,
+            order_id,
+            side,
+            price,
+            orig_size: size,
+            open_size: size,
+        }
+    }
+
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn order_id(&self) -> &'a str {
+        self.order_id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    pub fn size(&self) -> Price {
+        self.orig_size
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct OpenEvent<'a> {
     seq: Sequence,
     time: Timestamp,
@@ -163,6 +297,39 @@ pub struct OpenEvent<'a> {
     remaining_size: Price,
 }
 
+impl<'a> OpenEvent<'a> {
+    pub fn new(
+        seq: Sequence,
+        time: Timestamp,
+        order_id: &'a str,
+        remaining_size: Price,
+    ) -> OpenEvent<'a> {
+        OpenEvent {
+            seq,
+            time,
+            order_id,
+            remaining_size,
+        }
+    }
+
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn order_id(&self) -> &'a str {
+        self.order_id
+    }
+
+    pub fn remaining_size(&self) -> Price {
+        self.remaining_size
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct MatchEvent<'a> {
     seq: Sequence,
     time: Timestamp,
@@ -173,6 +340,58 @@ pub struct MatchEvent<'a> {
     size: Price,
 }
 
+impl<'a> MatchEvent<'a> {
+    pub fn new(
+        seq: Sequence,
+        time: Timestamp,
+        maker_order_id: &'a str,
+        taker_order_id: &'a str,
+        side: Side,
+        price: Price,
+        size: Price,
+    ) -> MatchEvent<'a> {
+        MatchEvent {
+            seq,
+            time,
+            maker_order_id,
+            taker_order_id,
+            side,
+            price,
+            size,
+        }
+    }
+
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn maker_order_id(&self) -> &'a str {
+        self.maker_order_id
+    }
+
+    pub fn taker_order_id(&self) -> &'a str {
+        self.taker_order_id
+    }
+
+    /// The side of the maker (resting) order.
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn size(&self) -> Price {
+        self.size
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct ChangeEvent<'a> {
     time: Timestamp,
     seq: Sequence,
@@ -182,6 +401,53 @@ pub struct ChangeEvent<'a> {
     new_size_or_funds: Price,
 }
 
+impl<'a> ChangeEvent<'a> {
+    pub fn new(
+        seq: Sequence,
+        time: Timestamp,
+        order_id: &'a str,
+        price: OrderPrice,
+        old_size_or_funds: Price,
+        new_size_or_funds: Price,
+    ) -> ChangeEvent<'a> {
+        ChangeEvent {
+            time,
+            seq,
+            order_id,
+            price,
+            old_size_or_funds,
+            new_size_or_funds,
+        }
+    }
+
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn order_id(&self) -> &'a str {
+        self.order_id
+    }
+
+    pub fn price(&self) -> OrderPrice {
+        self.price
+    }
+
+    /// The size before the change, or funds for a market order.
+    pub fn old_size_or_funds(&self) -> Price {
+        self.old_size_or_funds
+    }
+
+    /// The size after the change, or funds for a market order.
+    pub fn new_size_or_funds(&self) -> Price {
+        self.new_size_or_funds
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub struct DoneEvent<'a> {
     time: Timestamp,
     seq: Sequence,
@@ -189,6 +455,82 @@ pub struct DoneEvent<'a> {
     reason: DoneReason,
 }
 
+impl<'a> DoneEvent<'a> {
+    pub fn new(
+        seq: Sequence,
+        time: Timestamp,
+        order_id: &'a str,
+        reason: DoneReason,
+    ) -> DoneEvent<'a> {
+        DoneEvent {
+            time,
+            seq,
+            order_id,
+            reason,
+        }
+    }
+
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn order_id(&self) -> &'a str {
+        self.order_id
+    }
+
+    pub fn reason(&self) -> DoneReason {
+        self.reason
+    }
+}
+
+/// Any of the level 3 events, for passing them around before dispatching them
+/// to a listener.
+#[derive(Debug, PartialEq)]
+pub enum Level3Event<'a> {
+    Add(NewOrderEvent<'a>),
+    Open(OpenEvent<'a>),
+    Match(MatchEvent<'a>),
+    Change(ChangeEvent<'a>),
+    Done(DoneEvent<'a>),
+}
+
+impl<'a> Level3Event<'a> {
+    pub fn seq(&self) -> Sequence {
+        match *self {
+            Level3Event::Add(ref e) => e.seq,
+            Level3Event::Open(ref e) => e.seq,
+            Level3Event::Match(ref e) => e.seq,
+            Level3Event::Change(ref e) => e.seq,
+            Level3Event::Done(ref e) => e.seq,
+        }
+    }
+
+    pub fn time(&self) -> Timestamp {
+        match *self {
+            Level3Event::Add(ref e) => e.time,
+            Level3Event::Open(ref e) => e.time,
+            Level3Event::Match(ref e) => e.time,
+            Level3Event::Change(ref e) => e.time,
+            Level3Event::Done(ref e) => e.time,
+        }
+    }
+
+    /// Calls the listener method corresponding to this event.
+    pub fn dispatch<L: Level3FeedListener + ?Sized>(&self, listener: &mut L) {
+        match *self {
+            Level3Event::Add(ref e) => listener.on_add(e),
+            Level3Event::Open(ref e) => listener.on_open(e),
+            Level3Event::Match(ref e) => listener.on_match(e),
+            Level3Event::Change(ref e) => listener.on_change(e),
+            Level3Event::Done(ref e) => listener.on_done(e),
+        }
+    }
+}
+
 pub trait Level3FeedListener {
     fn on_add<'a>(&mut self, order: &NewOrderEvent<'a>);
     fn on_open<'a>(&mut self, event: &OpenEvent<'a>);
@@ -201,6 +543,9 @@ pub struct Book {
     bid: OrdersByPrice,
     ask: OrdersByPrice,
     orders: HashMap<String, Rc<RefCell<Order>>>,
+    best_bid: Option<BestLevel>,
+    best_ask: Option<BestLevel>,
+    level1_listener: Option<Box<Level1EventListener>>,
 }
 
 impl Book {
@@ -209,9 +554,46 @@ impl Book {
             bid: OrdersByPrice::new(),
             ask: OrdersByPrice::new(),
             orders: HashMap::new(),
+            best_bid: None,
+            best_ask: None,
+            level1_listener: None,
+        }
+    }
+
+    /// Sets a listener to be notified whenever the inside of the book changes.
+    pub fn set_level1_listener(&mut self, listener: Box<Level1EventListener>) {
+        self.level1_listener = Some(listener);
+    }
+
+    /// Replaces the orders of the book with those of `book`, e.g. one loaded
+    /// from a snapshot as of `seq` and `time`. The level 1 listener stays,
+    /// and is notified if that changes the inside.
+    pub fn replace_orders(&mut self, book: Book, seq: Sequence, time: Timestamp) {
+        let (old_bid, old_ask) = (self.best_bid, self.best_ask);
+        self.bid = book.bid;
+        self.ask = book.ask;
+        self.orders = book.orders;
+        self.best_bid = book.best_bid;
+        self.best_ask = book.best_ask;
+        if (old_bid, old_ask) != (self.best_bid, self.best_ask) {
+            self.notify_level1(seq, time);
         }
     }
 
+    pub fn best_bid(&self) -> Option<BestLevel> {
+        self.best_bid
+    }
+
+    pub fn best_ask(&self) -> Option<BestLevel> {
+        self.best_ask
+    }
+
+    /// Whether the book knows about the order, i.e. it has been added and is
+    /// not yet done.
+    pub fn has_order(&self, order_id: &str) -> bool {
+        self.orders.contains_key(order_id)
+    }
+
     pub fn price_level(&self, side: Side, px: Price) -> Option<&PriceLevel> {
         // Market order "levels" are not currently exposed.
         match side {
@@ -220,12 +602,140 @@ impl Book {
         }
     }
 
+    /// The limit levels of a side which have orders, from the inside out.
+    pub fn levels(&self, side: Side) -> Vec<(Price, &PriceLevel)> {
+        fn limit<'a>(
+            (px, level): (&OrderPrice, &'a PriceLevel),
+        ) -> Option<(Price, &'a PriceLevel)> {
+            match *px {
+                OrderPrice::Limit(price) if level.order_count() > 0 => Some((price, level)),
+                _ => None,
+            }
+        }
+        match side {
+            Side::Bid => self.bid.iter().rev().filter_map(limit).collect(),
+            Side::Ask => self.ask.iter().filter_map(limit).collect(),
+        }
+    }
+
+    /// Up to `count` limit levels of a side, from the inside out.
+    pub fn ladder(&self, side: Side, count: usize) -> Vec<LadderLevel> {
+        let mut cumulative = Price::zero();
+        self.levels(side)
+            .into_iter()
+            .take(count)
+            .map(|(price, level)| {
+                cumulative += level.open_size();
+                LadderLevel {
+                    price,
+                    size: level.open_size(),
+                    orders: level.order_count(),
+                    cumulative,
+                }
+            })
+            .collect()
+    }
+
+    /// The number of orders resting on a side.
+    pub fn order_count(&self, side: Side) -> usize {
+        self.levels(side).iter().map(|&(_, level)| level.order_count()).sum()
+    }
+
+    /// Halfway between the best bid and ask, if there are both.
+    pub fn mid(&self) -> Option<Price> {
+        match (self.best_bid, self.best_ask) {
+            (Some(bid), Some(ask)) => Some((bid.price + ask.price) / 2),
+            _ => None,
+        }
+    }
+
+    pub fn spread(&self) -> Option<Price> {
+        match (self.best_bid, self.best_ask) {
+            (Some(bid), Some(ask)) => Some(ask.price - bid.price),
+            _ => None,
+        }
+    }
+
+    /// The open size on a side at prices within `bps` basis points of the
+    /// mid, if there is one.
+    pub fn depth(&self, side: Side, bps: u32) -> Option<Price> {
+        let mid = self.mid()?.to_raw();
+        // Far enough from the mid to overflow means everything is within.
+        let offset = mid.checked_mul(i64::from(bps)).map_or(i64::max_value(), |o| o / 10000);
+        let within = |price: Price| match side {
+            Side::Bid => price.to_raw() >= mid.saturating_sub(offset),
+            Side::Ask => price.to_raw() <= mid.saturating_add(offset),
+        };
+        let mut depth = Price::zero();
+        for (price, level) in self.levels(side) {
+            if !within(price) {
+                break;
+            }
+            depth += level.open_size();
+        }
+        Some(depth)
+    }
+
     fn price_level_mut(&mut self, side: Side, px: OrderPrice) -> Option<&mut PriceLevel> {
         match side {
             Side::Bid => self.bid.get_mut(&px),
             Side::Ask => self.ask.get_mut(&px),
         }
     }
+
+    /// Finds the best non-empty limit level on a side, starting from the inside.
+    fn find_best(&self, side: Side) -> Option<BestLevel> {
+        let to_best = |(px, level): (&OrderPrice, &PriceLevel)| match *px {
+            OrderPrice::Limit(price) if level.open_size > Price::zero() => Some(BestLevel {
+                price,
+                size: level.open_size,
+            }),
+            _ => None,
+        };
+        match side {
+            Side::Bid => self.bid.iter().rev().filter_map(to_best).next(),
+            Side::Ask => self.ask.iter().filter_map(to_best).next(),
+        }
+    }
+
+    /// Called after a level on `side` at `px` was modified. Recomputes the inside
+    /// if the level could have affected it, and notifies the level 1 listener on
+    /// a change.
+    fn update_inside(&mut self, side: Side, px: OrderPrice, seq: Sequence, time: Timestamp) {
+        let price = match px {
+            OrderPrice::Limit(price) => price,
+            // Market orders never rest in the book, so they can't move the inside.
+            OrderPrice::Market => return,
+        };
+        let (old, touches_inside) = match side {
+            Side::Bid => (self.best_bid, self.best_bid.map_or(true, |b| price >= b.price)),
+            Side::Ask => (self.best_ask, self.best_ask.map_or(true, |b| price <= b.price)),
+        };
+        if !touches_inside {
+            return;
+        }
+
+        let new = self.find_best(side);
+        if new == old {
+            return;
+        }
+        match side {
+            Side::Bid => self.best_bid = new,
+            Side::Ask => self.best_ask = new,
+        }
+        self.notify_level1(seq, time);
+    }
+
+    fn notify_level1(&mut self, seq: Sequence, time: Timestamp) {
+        if let Some(ref mut listener) = self.level1_listener {
+            listener.on_level1_change(&Level1Event {
+                seq,
+                time,
+                bid: self.best_bid,
+                ask: self.best_ask,
+            });
+        }
+    }
 }
 
 impl Level3FeedListener for Book {
@@ -235,21 +745,25 @@ impl Level3FeedListener for Book {
     }
 
     fn on_open<'a>(&mut self, event: &OpenEvent<'a>) {
-        let shared_order = self.orders.get(event.order_id).expect("Unknown order ID");
+        let (side, px) = {
+            let shared_order = self.orders.get(event.order_id).expect("Unknown order ID");
 
-        {
-            let mut order = shared_order.borrow_mut();
-            order.on_open(event.remaining_size);
-        }
+            {
+                let mut order = shared_order.borrow_mut();
+                order.on_open(event.remaining_size);
+            }
 
-        let order = shared_order.borrow();
-        let entry = match order.side {
-            Side::Bid => self.bid.entry(order.price),
-            Side::Ask => self.ask.entry(order.price),
+            let order = shared_order.borrow();
+            let entry = match order.side {
+                Side::Bid => self.bid.entry(order.price),
+                Side::Ask => self.ask.entry(order.price),
+            };
+            entry
+                .or_insert(PriceLevel::new())
+                .on_open(shared_order.clone());
+            (order.side, order.price)
         };
-        entry
-            .or_insert(PriceLevel::new())
-            .on_open(shared_order.clone());
+        self.update_inside(side, px, event.seq, event.time);
     }
 
     fn on_match<'a>(&mut self, event: &MatchEvent<'a>) {
@@ -267,36 +781,47 @@ impl Level3FeedListener for Book {
         self.price_level_mut(maker_side, px)
             .expect("Price level with order doesn't exist!")
             .on_match_maker(event.size);
+        self.update_inside(maker_side, px, event.seq, event.time);
     }
 
     fn on_change<'a>(&mut self, event: &ChangeEvent<'a>) {
         let delta = event.new_size_or_funds - event.old_size_or_funds;
         assert!(delta <= Price::zero());
-        let (side, px) = {
+        let (side, px, resting) = {
             let mut order = self.orders
                 .get(event.order_id)
                 .expect("Unknown order ID")
                 .borrow_mut();
             order.on_change(delta);
-            (order.side, order.price)
+            (order.side, order.price, order.resting)
         };
+        // Orders which are received but not yet open can also change.
+        if !resting {
+            return;
+        }
         self.price_level_mut(side, px)
             .expect("Price level with order doesn't exist!")
             .on_change(delta);
+        self.update_inside(side, px, event.seq, event.time);
     }
 
     fn on_done<'a>(&mut self, event: &DoneEvent<'a>) {
-        let (side, px, size) = {
-            let mut order = self.orders
-                .get(event.order_id)
-                .expect("Unknown order ID")
-                .borrow_mut();
+        let shared_order = self.orders
+            .remove(event.order_id)
+            .expect("Unknown order ID");
+        let (side, px, size, resting) = {
+            let mut order = shared_order.borrow_mut();
             let size = order.on_done(event.reason);
-            (order.side, order.price, size)
+            (order.side, order.price, size, order.resting)
         };
+        // Orders that never rested (e.g. filled immediately) aren't in any level.
+        if !resting {
+            return;
+        }
         self.price_level_mut(side, px)
             .expect("Price level with order doesn't exist!")
-            .on_done(size);
+            .on_done(&shared_order, size);
+        self.update_inside(side, px, event.seq, event.time);
     }
 }
 
@@ -311,8 +836,8 @@ mod tests {
 
     fn new_event(order_id: &str, side: Side, price: OrderPrice, orig_size: Price) -> NewOrderEvent {
         NewOrderEvent {
-            seq: Sequence(0),
-            time: Timestamp(0),
+            seq: Sequence::from(0),
+            time: Timestamp::from_nanos(0),
             order_id,
             side,
             price,
@@ -323,8 +848,8 @@ mod tests {
 
     fn open_event(order_id: &str, remaining_size: Price) -> OpenEvent {
         OpenEvent {
-            seq: Sequence(0),
-            time: Timestamp(0),
+            seq: Sequence::from(0),
+            time: Timestamp::from_nanos(0),
             order_id,
             remaining_size,
         }
@@ -338,8 +863,8 @@ mod tests {
         size: Price,
     ) -> MatchEvent<'a> {
         MatchEvent {
-            seq: Sequence(0),
-            time: Timestamp(0),
+            seq: Sequence::from(0),
+            time: Timestamp::from_nanos(0),
             maker_order_id,
             taker_order_id,
             side,
@@ -355,8 +880,8 @@ mod tests {
         new_size_or_funds: Price,
     ) -> ChangeEvent {
         ChangeEvent {
-            seq: Sequence(0),
-            time: Timestamp(0),
+            seq: Sequence::from(0),
+            time: Timestamp::from_nanos(0),
             order_id,
             price,
             old_size_or_funds,
@@ -366,8 +891,8 @@ mod tests {
 
     fn done_event(order_id: &str, reason: DoneReason) -> DoneEvent {
         DoneEvent {
-            seq: Sequence(0),
-            time: Timestamp(0),
+            seq: Sequence::from(0),
+            time: Timestamp::from_nanos(0),
             order_id,
             reason,
         }
@@ -492,4 +1017,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn level1_changes() {
+        struct Recorder(Rc<RefCell<Vec<(Option<BestLevel>, Option<BestLevel>)>>>);
+        impl Level1EventListener for Recorder {
+            fn on_level1_change(&mut self, event: &Level1Event) {
+                self.0
+                    .borrow_mut()
+                    .push((event.best_bid(), event.best_ask()));
+            }
+        }
+        let best = |price: f64, size: f64| {
+            Some(BestLevel {
+                price: px(price),
+                size: px(size),
+            })
+        };
+
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let mut book = Book::new();
+        book.set_level1_listener(Box::new(Recorder(changes.clone())));
+
+        book.on_add(&new_event(&"order1", Side::Bid, Limit(px(10.00)), px(100.)));
+        book.on_open(&open_event(&"order1", px(100.)));
+        book.on_add(&new_event(&"order2", Side::Ask, Limit(px(10.05)), px(50.)));
+        book.on_open(&open_event(&"order2", px(50.)));
+        // Behind the inside; no change.
+        book.on_add(&new_event(&"order3", Side::Bid, Limit(px(9.95)), px(30.)));
+        book.on_open(&open_event(&"order3", px(30.)));
+        assert_eq!(2, changes.borrow().len());
+        assert_eq!((best(10.00, 100.), best(10.05, 50.)), changes.borrow()[1]);
+
+        book.on_add(&new_event(&"order4", Side::Ask, Market, px(40.)));
+        book.on_match(&match_event(
+            &"order1",
+            &"order4",
+            Side::Bid,
+            px(10.00),
+            px(40.),
+        ));
+        assert_eq!((best(10.00, 60.), best(10.05, 50.)), changes.borrow()[2]);
+
+        book.on_done(&done_event(&"order1", DoneReason::Canceled));
+        assert_eq!((best(9.95, 30.), best(10.05, 50.)), changes.borrow()[3]);
+
+        book.on_done(&done_event(&"order2", DoneReason::Canceled));
+        assert_eq!((best(9.95, 30.), None), changes.borrow()[4]);
+        assert_eq!(5, changes.borrow().len());
+        assert_eq!(best(9.95, 30.), book.best_bid());
+        assert_eq!(None, book.best_ask());
+
+        // Replacing the orders keeps the listener, which sees the new inside.
+        let mut other = Book::new();
+        other.on_add(&new_event(&"order5", Side::Ask, Limit(px(10.10)), px(20.)));
+        other.on_open(&open_event(&"order5", px(20.)));
+        book.replace_orders(other, Sequence::from(10), Timestamp::from_nanos(0));
+        assert_eq!((None, best(10.10, 20.)), changes.borrow()[5]);
+        assert!(!book.has_order("order3"));
+        book.on_done(&done_event(&"order5", DoneReason::Canceled));
+        assert_eq!((None, None), changes.borrow()[6]);
+    }
+
+    #[test]
+    fn ladder_and_depth() {
+        let mut book = Book::new();
+        let orders = [
+            ("b1", Side::Bid, 99.90, 1.),
+            ("b2", Side::Bid, 99.90, 2.),
+            ("b3", Side::Bid, 99.00, 4.),
+            ("a1", Side::Ask, 100.10, 1.5),
+            ("a2", Side::Ask, 101.50, 3.),
+        ];
+        for &(id, side, price, size) in &orders {
+            book.on_add(&new_event(id, side, Limit(px(price)), px(size)));
+            book.on_open(&open_event(id, px(size)));
+        }
+
+        assert_eq!(
+            vec![
+                LadderLevel {
+                    price: px(99.90),
+                    size: px(3.),
+                    orders: 2,
+                    cumulative: px(3.),
+                },
+                LadderLevel {
+                    price: px(99.00),
+                    size: px(4.),
+                    orders: 1,
+                    cumulative: px(7.),
+                },
+            ],
+            book.ladder(Side::Bid, 5)
+        );
+        assert_eq!(1, book.ladder(Side::Ask, 1).len());
+        assert_eq!(3, book.order_count(Side::Bid));
+        assert_eq!(Some(px(100.)), book.mid());
+        assert_eq!(Some(px(0.20)), book.spread());
+        assert_eq!(Some(px(3.)), book.depth(Side::Bid, 10));
+        assert_eq!(Some(px(1.5)), book.depth(Side::Ask, 10));
+        assert_eq!(Some(px(7.)), book.depth(Side::Bid, 100));
+        assert_eq!(Some(px(4.5)), book.depth(Side::Ask, 150));
+        assert_eq!(Some(px(7.)), book.depth(Side::Bid, u32::max_value()));
+        assert_eq!(Some(px(4.5)), book.depth(Side::Ask, u32::max_value()));
+        assert_eq!(None, Book::new().depth(Side::Bid, 10));
+    }
+
 }
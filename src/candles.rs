@@ -0,0 +1,446 @@
+/// Candles, i.e. open/high/low/close/volume bars, built from the match events
+/// of a level 3 feed.
+
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::str::FromStr;
+
+use book::{ChangeEvent, DoneEvent, Level3FeedListener, MatchEvent, NewOrderEvent, OpenEvent,
+           Timestamp};
+use price::Price;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// When a bar closes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Bars {
+    /// Bars covering fixed intervals of time, in nanoseconds, aligned to the
+    /// Unix epoch.
+    Time(u64),
+    /// Bars which close once their volume reaches at least this size. The
+    /// trade which reaches it isn't split.
+    Volume(Price),
+    /// Bars of this many trades.
+    Tick(u64),
+}
+
+impl FromStr for Bars {
+    type Err = String;
+    /// Parses an interval like `1s`, `1m`, `5m`, `1h` or `1d`, or
+    /// `volume:<size>` or `ticks:<trades>`.
+    fn from_str(s: &str) -> Result<Bars, String> {
+        let bad = || format!("Bad bars {:?}", s);
+        if s.starts_with("volume:") {
+            let size = Price::parse(&s["volume:".len()..]).map_err(|_| bad())?;
+            return if size > Price::zero() {
+                Ok(Bars::Volume(size))
+            } else {
+                Err(bad())
+            };
+        }
+        if s.starts_with("ticks:") {
+            return match s["ticks:".len()..].parse() {
+                Ok(trades) if trades > 0 => Ok(Bars::Tick(trades)),
+                _ => Err(bad()),
+            };
+        }
+        if s.is_empty() {
+            return Err(bad());
+        }
+        let (count, unit) = s.split_at(s.len() - 1);
+        let secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(bad()),
+        };
+        match count.parse::<u64>() {
+            Ok(count) if count > 0 => count
+                .checked_mul(secs * NANOS_PER_SEC)
+                .map(Bars::Time)
+                .ok_or_else(bad),
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// What to do with time intervals without any trades.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EmptyIntervals {
+    /// Leave them out.
+    Skip,
+    /// Fill them with a bar with no volume, priced at the previous close.
+    Carry,
+}
+
+impl FromStr for EmptyIntervals {
+    type Err = String;
+    fn from_str(s: &str) -> Result<EmptyIntervals, String> {
+        match s {
+            "skip" => Ok(EmptyIntervals::Skip),
+            "carry" => Ok(EmptyIntervals::Carry),
+            _ => Err(format!("Bad empty interval handling {:?}", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Candle {
+    /// The start of the interval for time bars, or the time of the first
+    /// trade otherwise.
+    pub start: Timestamp,
+    /// The end of the interval (exclusive) for time bars, or the time of the
+    /// last trade otherwise.
+    pub end: Timestamp,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Price,
+    pub trades: u64,
+    /// The sum of price times size over all trades, in raw units, for the
+    /// VWAP.
+    notional: i128,
+}
+
+impl Candle {
+    fn new(start: Timestamp, end: Timestamp, price: Price) -> Candle {
+        Candle {
+            start,
+            end,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: Price::zero(),
+            trades: 0,
+            notional: 0,
+        }
+    }
+
+    fn add(&mut self, time: Timestamp, price: Price, size: Price) {
+        if self.trades == 0 {
+            self.open = price;
+            self.high = price;
+            self.low = price;
+        }
+        if price > self.high {
+            self.high = price;
+        }
+        if price < self.low {
+            self.low = price;
+        }
+        self.close = price;
+        self.volume += size;
+        self.trades += 1;
+        self.notional += i128::from(price.to_raw()) * i128::from(size.to_raw());
+        // Only moves for volume and tick bars, since time bars end after
+        // their trades.
+        if self.end < time {
+            self.end = time;
+        }
+    }
+
+    /// The volume-weighted average price, or the close if there were no
+    /// trades.
+    pub fn vwap(&self) -> Price {
+        if self.volume == Price::zero() {
+            return self.close;
+        }
+        // Rounded to the nearest raw unit, like prices parsed from floats.
+        let volume = i128::from(self.volume.to_raw());
+        Price::from_raw(((self.notional + volume / 2) / volume) as i64)
+    }
+}
+
+/// Builds candles from the matches seen on the feed, which are assumed to be
+/// in time order.
+pub struct Candles {
+    bars: Bars,
+    empty: EmptyIntervals,
+    /// The bar trades are being added to.
+    current: Option<Candle>,
+    /// Bars which are complete.
+    closed: Vec<Candle>,
+}
+
+impl Candles {
+    /// Candles closing according to `bars`, skipping empty intervals.
+    pub fn new(bars: Bars) -> Candles {
+        Candles {
+            bars,
+            empty: EmptyIntervals::Skip,
+            current: None,
+            closed: Vec::new(),
+        }
+    }
+
+    /// Sets what to do with empty intervals of time bars.
+    pub fn with_empty_intervals(mut self, empty: EmptyIntervals) -> Candles {
+        self.empty = empty;
+        self
+    }
+
+    /// The candles completed so far.
+    pub fn candles(&self) -> &[Candle] {
+        &self.closed
+    }
+
+    /// Removes and returns the candles completed so far, e.g. to write them
+    /// out during a long replay.
+    pub fn take(&mut self) -> Vec<Candle> {
+        mem::replace(&mut self.closed, Vec::new())
+    }
+
+    /// The bar still open, if any.
+    pub fn current(&self) -> Option<&Candle> {
+        self.current.as_ref()
+    }
+
+    /// Closes time bars which end at or before `time`, as if the feed had
+    /// reached it without any more trades. With `EmptyIntervals::Carry`, this
+    /// includes filling the intervals up to it.
+    pub fn close_until(&mut self, time: Timestamp) {
+        let interval = match self.bars {
+            Bars::Time(interval) => interval,
+            _ => return,
+        };
+        while let Some(current) = self.current {
+            if current.end > time {
+                return;
+            }
+            self.closed.push(current);
+            self.current = match self.empty {
+                EmptyIntervals::Skip => None,
+                EmptyIntervals::Carry => Some(Candle::new(
+                    current.end,
+                    Timestamp::from_nanos(current.end.nanos() + interval),
+                    current.close,
+                )),
+            };
+        }
+    }
+
+    /// Closes the bar still open, however far along it is, unless it has no
+    /// trades.
+    pub fn finish(&mut self) {
+        if let Some(current) = self.current.take() {
+            if current.trades > 0 {
+                self.closed.push(current);
+            }
+        }
+    }
+
+    fn on_trade(&mut self, time: Timestamp, price: Price, size: Price) {
+        match self.bars {
+            Bars::Time(interval) => {
+                self.close_until(time);
+                if self.current.is_none() {
+                    let start = time.nanos() - time.nanos() % interval;
+                    self.current = Some(Candle::new(
+                        Timestamp::from_nanos(start),
+                        Timestamp::from_nanos(start + interval),
+                        price,
+                    ));
+                }
+                if let Some(ref mut current) = self.current {
+                    current.add(time, price, size);
+                }
+            }
+            Bars::Volume(_) | Bars::Tick(_) => {
+                let mut current = self.current
+                    .take()
+                    .unwrap_or_else(|| Candle::new(time, time, price));
+                current.add(time, price, size);
+                let full = match self.bars {
+                    Bars::Volume(threshold) => current.volume >= threshold,
+                    Bars::Tick(trades) => current.trades >= trades,
+                    Bars::Time(_) => unreachable!(),
+                };
+                if full {
+                    self.closed.push(current);
+                } else {
+                    self.current = Some(current);
+                }
+            }
+        }
+    }
+}
+
+impl Level3FeedListener for Candles {
+    fn on_add<'a>(&mut self, _event: &NewOrderEvent<'a>) {}
+    fn on_open<'a>(&mut self, _event: &OpenEvent<'a>) {}
+
+    fn on_match<'a>(&mut self, event: &MatchEvent<'a>) {
+        self.on_trade(event.time(), event.price(), event.size());
+    }
+
+    fn on_change<'a>(&mut self, _event: &ChangeEvent<'a>) {}
+    fn on_done<'a>(&mut self, _event: &DoneEvent<'a>) {}
+}
+
+/// Writes the header row of `write_csv`.
+pub fn write_csv_header<W: Write>(mut w: W) -> io::Result<()> {
+    writeln!(w, "product,start,end,open,high,low,close,volume,vwap,trades")
+}
+
+/// Writes a product's candles as CSV rows.
+pub fn write_csv<W: Write>(product: &str, candles: &[Candle], mut w: W) -> io::Result<()> {
+    for c in candles {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{},{}",
+            product,
+            c.start,
+            c.end,
+            c.open,
+            c.high,
+            c.low,
+            c.close,
+            c.volume,
+            c.vwap(),
+            c.trades
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use book::{Sequence, Side};
+
+    fn px(p: f64) -> Price {
+        Price::from(p)
+    }
+
+    fn ts(secs: u64) -> Timestamp {
+        let start = Timestamp::parse("2018-02-25T17:00:00Z").unwrap();
+        Timestamp::from_nanos(start.nanos() + secs * NANOS_PER_SEC)
+    }
+
+    fn trade(candles: &mut Candles, secs: u64, price: f64, size: f64) {
+        candles.on_match(&MatchEvent::new(
+            Sequence::from(secs),
+            ts(secs),
+            "maker",
+            "taker",
+            Side::Bid,
+            px(price),
+            px(size),
+        ));
+    }
+
+    fn trades(candles: &mut Candles) {
+        trade(candles, 5, 10., 1.);
+        trade(candles, 20, 12., 1.);
+        trade(candles, 59, 11., 2.);
+        trade(candles, 150, 9., 0.5);
+    }
+
+    #[test]
+    fn parse_bars() {
+        assert_eq!(Ok(Bars::Time(NANOS_PER_SEC)), "1s".parse());
+        assert_eq!(Ok(Bars::Time(300 * NANOS_PER_SEC)), "5m".parse());
+        assert_eq!(Ok(Bars::Volume(px(2.5))), "volume:2.5".parse());
+        assert_eq!(Ok(Bars::Tick(100)), "ticks:100".parse());
+        assert!("0m".parse::<Bars>().is_err());
+        assert!("5x".parse::<Bars>().is_err());
+        assert!("ticks:0".parse::<Bars>().is_err());
+        assert!("".parse::<Bars>().is_err());
+        assert!("999999999999d".parse::<Bars>().is_err());
+    }
+
+    #[test]
+    fn time_bars() {
+        let mut candles = Candles::new("1m".parse().unwrap());
+        trades(&mut candles);
+        assert_eq!(1, candles.candles().len());
+        let first = candles.candles()[0];
+        assert_eq!((ts(0), ts(60)), (first.start, first.end));
+        assert_eq!(
+            (px(10.), px(12.), px(10.), px(11.)),
+            (first.open, first.high, first.low, first.close)
+        );
+        assert_eq!((px(4.), 3), (first.volume, first.trades));
+        assert_eq!(px(11.), first.vwap());
+
+        // Still open until the interval has passed.
+        candles.close_until(ts(179));
+        assert_eq!(1, candles.candles().len());
+        candles.close_until(ts(180));
+        let last = candles.take();
+        assert_eq!(2, last.len());
+        assert_eq!((ts(120), px(9.), px(0.5)), (last[1].start, last[1].close, last[1].volume));
+        assert!(candles.candles().is_empty());
+    }
+
+    #[test]
+    fn carry_empty_intervals() {
+        let mut candles =
+            Candles::new("1m".parse().unwrap()).with_empty_intervals(EmptyIntervals::Carry);
+        trades(&mut candles);
+        candles.finish();
+        let bars: Vec<_> = candles
+            .candles()
+            .iter()
+            .map(|c| (c.start, c.open, c.close, c.volume, c.trades))
+            .collect();
+        assert_eq!(
+            vec![
+                (ts(0), px(10.), px(11.), px(4.), 3),
+                (ts(60), px(11.), px(11.), px(0.), 0),
+                (ts(120), px(9.), px(9.), px(0.5), 1),
+            ],
+            bars
+        );
+        assert_eq!(px(11.), candles.candles()[1].vwap());
+    }
+
+    #[test]
+    fn volume_and_tick_bars() {
+        let mut candles = Candles::new("volume:1.5".parse().unwrap());
+        trades(&mut candles);
+        assert_eq!(2, candles.candles().len());
+        // The trade reaching the threshold isn't split.
+        let bar = candles.candles()[0];
+        assert_eq!((ts(5), ts(20), px(2.), 2), (bar.start, bar.end, bar.volume, bar.trades));
+        assert_eq!(px(2.), candles.candles()[1].volume);
+        candles.finish();
+        assert_eq!(px(0.5), candles.candles()[2].volume);
+
+        let mut candles = Candles::new("ticks:3".parse().unwrap());
+        trades(&mut candles);
+        assert_eq!(1, candles.candles().len());
+        assert_eq!(Some(1), candles.current().map(|c| c.trades));
+    }
+
+    #[test]
+    fn exact_vwap() {
+        let mut candles = Candles::new("ticks:2".parse().unwrap());
+        trade(&mut candles, 1, 9999.99, 0.00000003);
+        trade(&mut candles, 2, 10000.01, 0.00000001);
+        // Notionals well beyond an i64 of raw units.
+        trade(&mut candles, 3, 90000.01, 1000.);
+        trade(&mut candles, 4, 90000.02, 3000.);
+        assert_eq!(px(9999.995), candles.candles()[0].vwap());
+        assert_eq!(px(90000.0175), candles.candles()[1].vwap());
+    }
+
+    #[test]
+    fn export() {
+        let mut candles = Candles::new("1m".parse().unwrap());
+        trades(&mut candles);
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv).unwrap();
+        write_csv("BTC-USD", candles.candles(), &mut csv).unwrap();
+        assert_eq!(
+            "product,start,end,open,high,low,close,volume,vwap,trades\n\
+             BTC-USD,2018-02-25T17:00:00.000000Z,2018-02-25T17:01:00.000000Z,\
+             10.00,12.00,10.00,11.00,4.00,11.00,3\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+}
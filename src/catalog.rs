@@ -0,0 +1,435 @@
+/// A catalog of the historical data in a store: which products it has, for
+/// which hours, and which files are missing.
+///
+/// Finding the products and sequence numbers in a chunk means decompressing
+/// all of it, so what's found is kept in a manifest. Updating the manifest
+/// only scans files it doesn't know about yet, or which may have changed.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json;
+
+use coinbase::BookSnapshot;
+use compression;
+use historical::feed::{DataStore, SnapshotFile};
+use historical::storage::Storage;
+use sequence::Sequence;
+
+pub const VERSION: u32 = 2;
+
+/// The name of the manifest file, in the root of a store.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The sequence numbers of a product's messages seen in some span of data.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SequenceRange {
+    pub first: Sequence,
+    pub last: Sequence,
+    pub messages: u64,
+}
+
+impl SequenceRange {
+    fn new(seq: Sequence) -> SequenceRange {
+        SequenceRange {
+            first: seq,
+            last: seq,
+            messages: 1,
+        }
+    }
+
+    /// Widens the range to include `other`.
+    fn extend(&mut self, other: &SequenceRange) {
+        if other.first < self.first {
+            self.first = other.first;
+        }
+        if other.last > self.last {
+            self.last = other.last;
+        }
+        self.messages += other.messages;
+    }
+}
+
+/// What was found in a chunk.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    pub hour: DateTime<Utc>,
+    /// The size of the chunk as stored, when it was scanned.
+    pub size: u64,
+    pub products: BTreeMap<String, SequenceRange>,
+    /// Why the chunk couldn't be read to the end, if it couldn't. The
+    /// products are those seen before that.
+    pub error: Option<String>,
+}
+
+/// What was found in a snapshot.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub product: String,
+    pub time: DateTime<Utc>,
+    /// The size of the snapshot as stored, when it was scanned.
+    pub size: u64,
+    /// None if the snapshot couldn't be read.
+    pub sequence: Option<Sequence>,
+}
+
+/// The files of a store which have been scanned, by path relative to the
+/// store's root.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub chunks: BTreeMap<String, ChunkEntry>,
+    pub snapshots: BTreeMap<String, SnapshotEntry>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The fields of a message needed for the catalog.
+#[derive(Deserialize)]
+struct Header {
+    product_id: Option<String>,
+    sequence: Option<u64>,
+}
+
+/// Collects the sequence numbers of each product in a chunk. Lines which
+/// aren't messages with both are skipped.
+fn scan_chunk<R: Read>(r: R, products: &mut BTreeMap<String, SequenceRange>) -> io::Result<()> {
+    for line in BufReader::new(r).lines() {
+        let header = match serde_json::from_str::<Header>(&line?) {
+            Ok(header) => header,
+            Err(_) => continue,
+        };
+        if let (Some(product), Some(seq)) = (header.product_id, header.sequence) {
+            let range = SequenceRange::new(Sequence::from(seq));
+            products
+                .entry(product)
+                .and_modify(|r| r.extend(&range))
+                .or_insert(range);
+        }
+    }
+    Ok(())
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest {
+            version: VERSION,
+            chunks: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+        }
+    }
+
+    pub fn read<R: Read>(r: R) -> io::Result<Manifest> {
+        let manifest: Manifest =
+            serde_json::from_reader(r).map_err(|e| invalid(format!("Bad manifest: {}", e)))?;
+        if manifest.version != VERSION {
+            return Err(invalid(format!(
+                "Unsupported manifest version {}",
+                manifest.version
+            )));
+        }
+        Ok(manifest)
+    }
+
+    pub fn write<W: Write>(&self, w: W) -> io::Result<()> {
+        serde_json::to_writer(w, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Brings the manifest up to date with the files in `store`. Files it
+    /// doesn't have are scanned, and those no longer in the store are
+    /// dropped. Returns the number of files scanned.
+    ///
+    /// Known files are scanned again if their size has changed, or if they
+    /// couldn't be read last time. So is the chunk of the latest hour, which
+    /// may still be being written, even though its compressed size hasn't
+    /// changed yet.
+    pub fn update<S: Storage>(&mut self, store: &DataStore<S>) -> io::Result<usize> {
+        let relative = |path: &Path| {
+            path.strip_prefix(store.root())
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let glob = |pattern: &str| {
+            store
+                .storage()
+                .glob(pattern)
+                .map_err(|e| invalid(format!("Bad glob {:?}: {}", pattern, e)))
+        };
+        let mut scanned = 0;
+
+        let mut paths = Vec::new();
+        for entry in glob(&store.chunk_glob())? {
+            let path = entry.map_err(|e| io::Error::new(e.error().kind(), e.to_string()))?;
+            if let Some(hour) = store.parse_chunk_path(&path) {
+                paths.push((path, hour));
+            }
+        }
+        let latest = paths.iter().map(|&(_, hour)| hour).max();
+
+        let mut chunks = HashSet::new();
+        for (path, hour) in paths {
+            let name = relative(&path);
+            chunks.insert(name.clone());
+            let size = store.storage().size(&path)?;
+            let known = self.chunks.get(&name).map_or(false, |entry| {
+                entry.size == size && entry.error.is_none() && Some(hour) != latest
+            });
+            if known {
+                continue;
+            }
+            let mut products = BTreeMap::new();
+            let result = store
+                .storage()
+                .open(&path)
+                .and_then(|f| compression::decompress(&path, f))
+                .and_then(|r| scan_chunk(r, &mut products));
+            let entry = ChunkEntry {
+                hour,
+                size,
+                products,
+                error: result.err().map(|e| e.to_string()),
+            };
+            self.chunks.insert(name, entry);
+            scanned += 1;
+        }
+        self.chunks.retain(|name, _| chunks.contains(name));
+
+        let mut snapshots = HashSet::new();
+        for (product, SnapshotFile { path, time }) in store.snapshots(None)? {
+            let name = relative(&path);
+            snapshots.insert(name.clone());
+            let size = store.storage().size(&path)?;
+            let known = self.snapshots
+                .get(&name)
+                .map_or(false, |entry| entry.size == size && entry.sequence.is_some());
+            if known {
+                continue;
+            }
+            let sequence = store
+                .storage()
+                .open(&path)
+                .and_then(|f| compression::decompress(&path, f))
+                .and_then(BookSnapshot::read)
+                .map(|snapshot| snapshot.seq())
+                .ok();
+            let entry = SnapshotEntry {
+                product,
+                time,
+                size,
+                sequence,
+            };
+            self.snapshots.insert(name, entry);
+            scanned += 1;
+        }
+        self.snapshots.retain(|name, _| snapshots.contains(name));
+
+        Ok(scanned)
+    }
+
+    /// Summarizes the manifest by product.
+    pub fn catalog(&self) -> Catalog {
+        let mut products: BTreeMap<String, ProductCoverage> = BTreeMap::new();
+        let mut unreadable = Vec::new();
+        let hours: Vec<DateTime<Utc>> = self.chunks.values().map(|c| c.hour).collect();
+        for (name, chunk) in &self.chunks {
+            if chunk.error.is_some() {
+                unreadable.push(name.clone());
+            }
+            for (product, range) in &chunk.products {
+                let coverage = products
+                    .entry(product.clone())
+                    .or_insert_with(|| ProductCoverage::new(product));
+                coverage.hours.insert(chunk.hour, *range);
+            }
+        }
+        for (name, snapshot) in &self.snapshots {
+            if snapshot.sequence.is_none() {
+                unreadable.push(name.clone());
+            }
+            products
+                .entry(snapshot.product.clone())
+                .or_insert_with(|| ProductCoverage::new(&snapshot.product))
+                .snapshots
+                .push(snapshot.clone());
+        }
+
+        for coverage in products.values_mut() {
+            coverage.snapshots.sort_by_key(|s| s.time);
+            coverage.missing_hours = missing_hours(coverage.hours.keys().cloned());
+        }
+        Catalog {
+            first_hour: hours.iter().min().cloned(),
+            last_hour: hours.iter().max().cloned(),
+            missing_chunks: missing_hours(hours.into_iter()),
+            unreadable,
+            products,
+        }
+    }
+}
+
+/// The hours between the first and last of `hours` which aren't among them.
+fn missing_hours<I: Iterator<Item = DateTime<Utc>>>(hours: I) -> Vec<DateTime<Utc>> {
+    let mut hours: Vec<DateTime<Utc>> = hours.collect();
+    hours.sort();
+    let mut missing = Vec::new();
+    for pair in hours.windows(2) {
+        let mut hour = pair[0] + Duration::hours(1);
+        while hour < pair[1] {
+            missing.push(hour);
+            hour = hour + Duration::hours(1);
+        }
+    }
+    missing
+}
+
+/// The data a store has for one product.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProductCoverage {
+    pub product: String,
+    /// The hours with messages for the product, and their sequence numbers.
+    pub hours: BTreeMap<DateTime<Utc>, SequenceRange>,
+    /// Snapshots of the product, in time order.
+    pub snapshots: Vec<SnapshotEntry>,
+    /// Hours without messages for the product, between the first and last
+    /// hours which have some.
+    pub missing_hours: Vec<DateTime<Utc>>,
+}
+
+impl ProductCoverage {
+    fn new(product: &str) -> ProductCoverage {
+        ProductCoverage {
+            product: product.to_owned(),
+            hours: BTreeMap::new(),
+            snapshots: Vec::new(),
+            missing_hours: Vec::new(),
+        }
+    }
+
+    /// The sequence numbers seen over all hours.
+    pub fn sequences(&self) -> Option<SequenceRange> {
+        let mut ranges = self.hours.values();
+        let mut all = *ranges.next()?;
+        for range in ranges {
+            all.extend(range);
+        }
+        Some(all)
+    }
+
+    /// Whether there are messages for the product in the hour containing
+    /// `time`.
+    pub fn covers(&self, time: DateTime<Utc>) -> bool {
+        self.hours
+            .keys()
+            .any(|&hour| hour <= time && time < hour + Duration::hours(1))
+    }
+}
+
+/// What a store has, by product, and what it's missing.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Catalog {
+    pub first_hour: Option<DateTime<Utc>>,
+    pub last_hour: Option<DateTime<Utc>>,
+    /// Hours without a chunk, between the first and last chunks.
+    pub missing_chunks: Vec<DateTime<Utc>>,
+    /// Files which couldn't be read, relative to the store's root.
+    pub unreadable: Vec<String>,
+    pub products: BTreeMap<String, ProductCoverage>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use compression::Compression;
+    use historical::storage::Memory;
+
+    fn gzip(contents: &str) -> Vec<u8> {
+        compression::compress(Compression::Gzip, contents.as_bytes()).unwrap()
+    }
+
+    fn line(product: &str, seq: u64) -> String {
+        format!(
+            "{{\"type\":\"done\",\"product_id\":\"{}\",\"sequence\":{}}}\n",
+            product, seq
+        )
+    }
+
+    #[test]
+    fn scan_and_update() {
+        let mut storage = Memory::new();
+        let chunk = [
+            "{\"type\":\"subscriptions\",\"channels\":[]}\n".to_owned(),
+            line("BTC-USD", 10),
+            line("BTC-EUR", 3),
+            line("BTC-USD", 12),
+        ].concat();
+        storage.insert("data/ws_20180303_000000.txt.gz", gzip(&chunk));
+        storage.insert("data/ws_20180303_030000.txt.gz", gzip(&line("BTC-USD", 20)));
+        storage.insert("data/ws_20180303_040000.txt.gz", b"not gzip".to_vec());
+        storage.insert(
+            "data/BTC-EUR_20180303_000010.json.gz",
+            gzip(r#"{"sequence":2,"bids":[],"asks":[]}"#),
+        );
+        let store = DataStore::with_storage(storage.clone(), "data");
+
+        let mut manifest = Manifest::new();
+        assert_eq!(4, manifest.update(&store).unwrap());
+        // The latest chunk is scanned again, the more so since it's
+        // unreadable.
+        assert_eq!(1, manifest.update(&store).unwrap());
+
+        let hour = |h| Utc.ymd(2018, 3, 3).and_hms(h, 0, 0);
+        let catalog = manifest.catalog();
+        assert_eq!(Some(hour(0)), catalog.first_hour);
+        assert_eq!(Some(hour(4)), catalog.last_hour);
+        assert_eq!(vec![hour(1), hour(2)], catalog.missing_chunks);
+        assert_eq!(vec!["ws_20180303_040000.txt.gz"], catalog.unreadable);
+
+        let btc_usd = &catalog.products["BTC-USD"];
+        assert_eq!(vec![hour(0), hour(3)], btc_usd.hours.keys().cloned().collect::<Vec<_>>());
+        assert_eq!(
+            Some(SequenceRange {
+                first: Sequence::from(10),
+                last: Sequence::from(20),
+                messages: 3,
+            }),
+            btc_usd.sequences()
+        );
+        assert_eq!(vec![hour(1), hour(2)], btc_usd.missing_hours);
+        assert!(btc_usd.covers(Utc.ymd(2018, 3, 3).and_hms(3, 59, 59)));
+        assert!(!btc_usd.covers(hour(1)));
+
+        let btc_eur = &catalog.products["BTC-EUR"];
+        assert_eq!(1, btc_eur.hours.len());
+        assert_eq!(Some(Sequence::from(2)), btc_eur.snapshots[0].sequence);
+        assert!(btc_eur.missing_hours.is_empty());
+
+        // Besides the latest chunk, only new files and those whose size has
+        // changed are scanned, and deleted ones are dropped.
+        storage.insert("data/ws_20180303_010000.txt.gz", gzip(&line("BTC-EUR", 4)));
+        storage.insert("data/ws_20180303_030000.txt.gz", gzip(&(line("BTC-USD", 20) + &line("BTC-USD", 21))));
+        storage.insert("data/ws_20180303_040000.txt.gz", gzip(&line("BTC-USD", 22)));
+        let mut json = Vec::new();
+        manifest.write(&mut json).unwrap();
+        let mut manifest = Manifest::read(&json[..]).unwrap();
+        assert_eq!(3, manifest.update(&DataStore::with_storage(storage, "data")).unwrap());
+        let catalog = manifest.catalog();
+        assert_eq!(vec![hour(2)], catalog.missing_chunks);
+        assert!(catalog.unreadable.is_empty());
+        assert_eq!(Sequence::from(22), catalog.products["BTC-USD"].sequences().unwrap().last);
+
+        let mut fewer = Memory::new();
+        fewer.insert("data/ws_20180303_010000.txt.gz", gzip(""));
+        assert_eq!(1, manifest.update(&DataStore::with_storage(fewer, "data")).unwrap());
+        assert_eq!(1, manifest.chunks.len());
+        assert!(manifest.snapshots.is_empty());
+    }
+}
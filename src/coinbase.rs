@@ -0,0 +1,356 @@
+/// Decoding of Coinbase (GDAX) full channel messages and level 3 book snapshots.
+
+use std::io;
+use std::io::{Cursor, Read};
+
+use serde_json;
+
+use book::{Book, ChangeEvent, DoneEvent, DoneReason, Level3Event, Level3FeedListener,
+           MatchEvent, NewOrderEvent, OpenEvent, OrderPrice, Sequence, Side, Timestamp};
+use price::Price;
+
+/// A message from the full channel. Fields which don't apply to a message's
+/// type are None.
+#[derive(Deserialize, Debug)]
+pub struct Message {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub sequence: Option<u64>,
+    pub time: Option<String>,
+    pub product_id: Option<String>,
+    pub order_id: Option<String>,
+    pub order_type: Option<String>,
+    pub side: Option<String>,
+    pub price: Option<String>,
+    pub size: Option<String>,
+    pub funds: Option<String>,
+    pub remaining_size: Option<String>,
+    pub reason: Option<String>,
+    pub maker_order_id: Option<String>,
+    pub taker_order_id: Option<String>,
+    pub new_size: Option<String>,
+    pub old_size: Option<String>,
+    pub new_funds: Option<String>,
+    pub old_funds: Option<String>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn required<'a>(field: &'a Option<String>, name: &str) -> io::Result<&'a str> {
+    match *field {
+        Some(ref val) => Ok(val),
+        None => Err(invalid(format!("Message is missing {}", name))),
+    }
+}
+
+fn parse_price(field: &Option<String>, name: &str) -> io::Result<Price> {
+    let s = required(field, name)?;
+    Price::parse(s).map_err(|e| invalid(format!("Bad {} {:?}: {}", name, s, e)))
+}
+
+impl Message {
+    pub fn parse(line: &str) -> io::Result<Message> {
+        serde_json::from_str(line).map_err(|e| invalid(format!("Bad message: {}", e)))
+    }
+
+    pub fn seq(&self) -> io::Result<Sequence> {
+        match self.sequence {
+            Some(seq) => Ok(Sequence::from(seq)),
+            None => Err(invalid("Message is missing sequence".to_owned())),
+        }
+    }
+
+    pub fn timestamp(&self) -> io::Result<Timestamp> {
+        let time = required(&self.time, "time")?;
+        Timestamp::parse(time).map_err(|e| invalid(format!("Bad time {:?}: {}", time, e)))
+    }
+
+    fn book_side(&self) -> io::Result<Side> {
+        match required(&self.side, "side")? {
+            "buy" => Ok(Side::Bid),
+            "sell" => Ok(Side::Ask),
+            side => Err(invalid(format!("Bad side {:?}", side))),
+        }
+    }
+
+    /// Converts the message to a level 3 event. Returns None for messages
+    /// that aren't level 3 events, like heartbeats and subscription
+    /// acknowledgements.
+    pub fn to_event(&self) -> io::Result<Option<Level3Event>> {
+        if !["received", "open", "match", "change", "done"].contains(&&*self.kind) {
+            return Ok(None);
+        }
+        let (seq, time) = (self.seq()?, self.timestamp()?);
+        let event = match &*self.kind {
+            "received" => {
+                let (price, size) = match self.order_type.as_ref().map(|t| &**t) {
+                    Some("market") => match self.size {
+                        Some(_) => (OrderPrice::Market, parse_price(&self.size, "size")?),
+                        None => (OrderPrice::Market, parse_price(&self.funds, "funds")?),
+                    },
+                    _ => (
+                        OrderPrice::Limit(parse_price(&self.price, "price")?),
+                        parse_price(&self.size, "size")?,
+                    ),
+                };
+                Level3Event::Add(NewOrderEvent::new(
+                    seq,
+                    time,
+                    required(&self.order_id, "order_id")?,
+                    self.book_side()?,
+                    price,
+                    size,
+                ))
+            }
+            "open" => Level3Event::Open(OpenEvent::new(
+                seq,
+                time,
+                required(&self.order_id, "order_id")?,
+                parse_price(&self.remaining_size, "remaining_size")?,
+            )),
+            "match" => Level3Event::Match(MatchEvent::new(
+                seq,
+                time,
+                required(&self.maker_order_id, "maker_order_id")?,
+                required(&self.taker_order_id, "taker_order_id")?,
+                self.book_side()?,
+                parse_price(&self.price, "price")?,
+                parse_price(&self.size, "size")?,
+            )),
+            "change" => {
+                let price = match self.price {
+                    Some(_) => OrderPrice::Limit(parse_price(&self.price, "price")?),
+                    None => OrderPrice::Market,
+                };
+                let (old, new) = match self.new_size {
+                    Some(_) => (
+                        parse_price(&self.old_size, "old_size")?,
+                        parse_price(&self.new_size, "new_size")?,
+                    ),
+                    None => (
+                        parse_price(&self.old_funds, "old_funds")?,
+                        parse_price(&self.new_funds, "new_funds")?,
+                    ),
+                };
+                Level3Event::Change(ChangeEvent::new(
+                    seq,
+                    time,
+                    required(&self.order_id, "order_id")?,
+                    price,
+                    old,
+                    new,
+                ))
+            }
+            _ => {
+                let reason = match required(&self.reason, "reason")? {
+                    "filled" => DoneReason::Filled,
+                    "canceled" => DoneReason::Canceled,
+                    reason => return Err(invalid(format!("Bad reason {:?}", reason))),
+                };
+                Level3Event::Done(DoneEvent::new(
+                    seq,
+                    time,
+                    required(&self.order_id, "order_id")?,
+                    reason,
+                ))
+            }
+        };
+        Ok(Some(event))
+    }
+
+    /// For an open message, the event adding the order to a book which
+    /// doesn't know about it yet. This happens when replaying from a snapshot
+    /// taken after the order was received but before it was opened.
+    pub fn to_new_order_for_open(&self) -> io::Result<NewOrderEvent> {
+        Ok(NewOrderEvent::new(
+            self.seq()?,
+            self.timestamp()?,
+            required(&self.order_id, "order_id")?,
+            self.book_side()?,
+            OrderPrice::Limit(parse_price(&self.price, "price")?),
+            parse_price(&self.remaining_size, "remaining_size")?,
+        ))
+    }
+}
+
+/// A level 3 book snapshot, as returned by the REST API. Each entry of bids
+/// and asks is `[price, size, order_id]`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BookSnapshot {
+    pub sequence: u64,
+    pub bids: Vec<Vec<String>>,
+    pub asks: Vec<Vec<String>>,
+}
+
+/// How much of the start of a snapshot to scan for its sequence number.
+const SEQUENCE_SCAN_BYTES: u64 = 256;
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    sequence: u64,
+}
+
+impl BookSnapshot {
+    pub fn read<R: Read>(r: R) -> io::Result<BookSnapshot> {
+        serde_json::from_reader(r).map_err(|e| invalid(format!("Bad snapshot: {}", e)))
+    }
+
+    /// Reads just the sequence number of a snapshot. Coinbase and
+    /// checkpoints put it before the bids and asks, so it's found without
+    /// reading any further. Otherwise the rest is parsed, but not kept.
+    pub fn read_sequence<R: Read>(mut r: R) -> io::Result<Sequence> {
+        let mut head = Vec::new();
+        (&mut r).take(SEQUENCE_SCAN_BYTES).read_to_end(&mut head)?;
+        if let Some(seq) = scan_sequence(&head) {
+            return Ok(seq);
+        }
+        let header: SnapshotHeader = serde_json::from_reader(Cursor::new(head).chain(r))
+            .map_err(|e| invalid(format!("Bad snapshot: {}", e)))?;
+        Ok(Sequence::from(header.sequence))
+    }
+
+    /// A snapshot of the orders resting on `book`, which is up to date as of
+    /// `seq`.
+    pub fn from_book(book: &Book, seq: Sequence) -> BookSnapshot {
+        let entries = |side| {
+            let mut entries = Vec::new();
+            for (price, level) in book.levels(side) {
+                for order in level.orders() {
+                    entries.push(vec![
+                        price.to_string(),
+                        order.open_size().to_string(),
+                        order.id().to_owned(),
+                    ]);
+                }
+            }
+            entries
+        };
+        BookSnapshot {
+            sequence: u64::from(seq),
+            bids: entries(Side::Bid),
+            asks: entries(Side::Ask),
+        }
+    }
+
+    pub fn seq(&self) -> Sequence {
+        Sequence::from(self.sequence)
+    }
+
+    /// Builds a book containing the orders of the snapshot. Each order is
+    /// added and opened with the snapshot's sequence number, and `time`.
+    pub fn to_book(&self, time: Timestamp) -> io::Result<Book> {
+        let mut book = Book::new();
+        for &(side, entries) in &[(Side::Bid, &self.bids), (Side::Ask, &self.asks)] {
+            for entry in entries {
+                if entry.len() < 3 {
+                    return Err(invalid(format!("Bad snapshot entry {:?}", entry)));
+                }
+                let parse = |s: &str| {
+                    Price::parse(s)
+                        .map_err(|e| invalid(format!("Bad snapshot entry {:?}: {}", entry, e)))
+                };
+                let (price, size, order_id) = (parse(&entry[0])?, parse(&entry[1])?, &entry[2]);
+                book.on_add(&NewOrderEvent::new(
+                    self.seq(),
+                    time,
+                    order_id,
+                    side,
+                    OrderPrice::Limit(price),
+                    size,
+                ));
+                book.on_open(&OpenEvent::new(self.seq(), time, order_id, size));
+            }
+        }
+        Ok(book)
+    }
+}
+
+/// Finds the value of the `sequence` key in the start of a snapshot, if it's
+/// there in full.
+fn scan_sequence(head: &[u8]) -> Option<Sequence> {
+    const KEY: &[u8] = b"\"sequence\":";
+    let start = head.windows(KEY.len()).position(|w| w == KEY)? + KEY.len();
+    let value = &head[start..];
+    let value = &value[value.iter().position(|&c| c != b' ')?..];
+    let len = value.iter().position(|c| !c.is_ascii_digit())?;
+    let digits = ::std::str::from_utf8(&value[..len]).ok()?;
+    digits.parse::<u64>().ok().map(Sequence::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(p: f64) -> Price {
+        Price::from(p)
+    }
+
+    #[test]
+    fn decode_events() {
+        let received = Message::parse(
+            r#"{"type":"received","time":"2018-02-25T17:00:00.100000Z","product_id":"BTC-USD","sequence":10,"order_id":"o1","size":"1.5","price":"10.00","side":"buy","order_type":"limit"}"#,
+        ).unwrap();
+        match received.to_event().unwrap() {
+            Some(Level3Event::Add(ref e)) => assert_eq!("o1", e.order_id()),
+            _ => panic!("Expected an add event"),
+        }
+
+        let done = Message::parse(
+            r#"{"type":"done","time":"2018-02-25T17:00:00.200000Z","product_id":"BTC-USD","sequence":11,"price":"10.00","order_id":"o1","reason":"canceled","side":"buy","remaining_size":"1.5"}"#,
+        ).unwrap();
+        let event = done.to_event().unwrap().unwrap();
+        assert_eq!(Sequence::from(11), event.seq());
+        assert_eq!(
+            Timestamp::parse("2018-02-25T17:00:00.2Z").unwrap(),
+            event.time()
+        );
+
+        let heartbeat = Message::parse(r#"{"type":"heartbeat","sequence":12}"#).unwrap();
+        assert!(heartbeat.to_event().unwrap().is_none());
+
+        let bad = Message::parse(r#"{"type":"open","sequence":13}"#).unwrap();
+        assert!(bad.to_event().is_err());
+    }
+
+    #[test]
+    fn snapshot_to_book() {
+        let snapshot = BookSnapshot::read(
+            r#"{"sequence":100,"bids":[["10.00","1.0","b1"],["10.00","0.5","b2"],["9.50","2.0","b3"]],"asks":[["10.10","2.0","a1"]]}"#.as_bytes(),
+        ).unwrap();
+        let book = snapshot.to_book(Timestamp::from_nanos(0)).unwrap();
+        assert_eq!(px(10.00), book.best_bid().unwrap().price);
+        assert_eq!(px(1.5), book.best_bid().unwrap().size);
+        assert_eq!(px(10.10), book.best_ask().unwrap().price);
+        assert_eq!(2, book.price_level(Side::Bid, px(10.00)).unwrap().order_count());
+        assert!(book.has_order("b3"));
+
+        let copy = BookSnapshot::from_book(&book, Sequence::from(120));
+        assert_eq!(120, copy.sequence);
+        assert_eq!(
+            vec![
+                vec!["10.00", "1.00", "b1"],
+                vec!["10.00", "0.50", "b2"],
+                vec!["9.50", "2.00", "b3"],
+            ],
+            copy.bids
+        );
+        assert_eq!(vec![vec!["10.10", "2.00", "a1"]], copy.asks);
+    }
+
+    #[test]
+    fn snapshot_sequence() {
+        let read = |s: &str| BookSnapshot::read_sequence(s.as_bytes());
+        assert_eq!(Sequence::from(100), read(r#"{"sequence":100,"bids":[],"asks":[]}"#).unwrap());
+        assert_eq!(Sequence::from(7), read(r#"{"sequence": 7, "bids": [], "asks": []}"#).unwrap());
+
+        // Past the part that's scanned, so it's parsed instead.
+        let late = format!(
+            r#"{{"bids":[{}],"asks":[],"sequence":12}}"#,
+            vec![r#"["1.00","1.0","id"]"#; 20].join(",")
+        );
+        assert_eq!(Sequence::from(12), read(&late).unwrap());
+        assert!(read(r#"{"bids":[],"asks":[]}"#).is_err());
+    }
+}
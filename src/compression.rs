@@ -0,0 +1,170 @@
+/// Transparent decompression of data files, which may be gzip, zstd or plain.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Cursor, Read, Write};
+use std::path::Path;
+
+use flate2;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use zstd;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression implied by a file's extension, if it names one.
+    /// Extensions like `.txt` or `.json` don't say anything either way.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Compression> {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") | Some("zstd") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The compression of data starting with `bytes`. Anything that isn't
+    /// recognised is taken to be uncompressed.
+    pub fn from_magic(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Detects the compression of the file at `path`, from its extension or
+/// else by peeking at the first bytes of `r`.
+pub fn detect<P: AsRef<Path>, B: BufRead>(path: P, r: &mut B) -> io::Result<Compression> {
+    match Compression::from_extension(path) {
+        Some(compression) => Ok(compression),
+        None => Ok(Compression::from_magic(r.fill_buf()?)),
+    }
+}
+
+/// Wraps `r`, the contents of the file at `path`, in the right decoder. The
+/// format is taken from the file's extension when it has a compressed one,
+/// and otherwise from the first bytes of the contents.
+pub fn decompress<P: AsRef<Path>, R: Read + 'static>(path: P, r: R) -> io::Result<Box<Read>> {
+    match Compression::from_extension(path) {
+        Some(compression) => decoder(compression, r),
+        None => sniff(r),
+    }
+}
+
+/// Opens the file at `path` for reading its decompressed contents.
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<Read>> {
+    let f = File::open(&path)?;
+    decompress(path, f)
+}
+
+/// Detects the format from the first bytes of `r`, then puts them back in
+/// front of the rest for the decoder.
+fn sniff<R: Read + 'static>(mut r: R) -> io::Result<Box<Read>> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut r)
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let compression = Compression::from_magic(&magic);
+    decoder(compression, Cursor::new(magic).chain(r))
+}
+
+/// Compresses all of `data` at once.
+pub fn compress(compression: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Gzip => {
+            let mut e = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            e.write_all(data)?;
+            e.finish()
+        }
+        Compression::Zstd => zstd::stream::encode_all(data, 0),
+    }
+}
+
+/// Wraps `r` in the decoder for `compression`. Gzip files may have several
+/// members, and zstd files several frames, which are read one after another.
+pub fn decoder<R: Read + 'static>(compression: Compression, r: R) -> io::Result<Box<Read>> {
+    Ok(match compression {
+        Compression::None => Box::new(r),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(r)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(r)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &str = "{\"type\":\"heartbeat\"}\n{\"type\":\"heartbeat\"}\n";
+
+    fn gzip(s: &str) -> Vec<u8> {
+        compress(Compression::Gzip, s.as_bytes()).unwrap()
+    }
+
+    fn zstd(s: &str) -> Vec<u8> {
+        compress(Compression::Zstd, s.as_bytes()).unwrap()
+    }
+
+    fn read(path: &str, data: Vec<u8>) -> String {
+        let mut s = String::new();
+        decompress(path, Cursor::new(data))
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        s
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(Some(Compression::Gzip), Compression::from_extension("ws.txt.gz"));
+        assert_eq!(Some(Compression::Zstd), Compression::from_extension("ws.txt.zst"));
+        assert_eq!(None, Compression::from_extension("ws.txt"));
+        assert_eq!(Compression::Gzip, Compression::from_magic(&gzip("")));
+        assert_eq!(Compression::Zstd, Compression::from_magic(&zstd("")));
+        assert_eq!(Compression::None, Compression::from_magic(b"{}"));
+        assert_eq!(Compression::None, Compression::from_magic(b""));
+    }
+
+    #[test]
+    fn uncompressed() {
+        assert_eq!(CONTENTS, read("data/ws.txt", CONTENTS.as_bytes().to_vec()));
+        assert_eq!("{", read("data/ws.txt", b"{".to_vec()));
+        assert_eq!("", read("data/ws.txt", Vec::new()));
+    }
+
+    #[test]
+    fn gzipped() {
+        assert_eq!(CONTENTS, read("data/ws.txt.gz", gzip(CONTENTS)));
+        assert_eq!(CONTENTS, read("data/ws.txt", gzip(CONTENTS)));
+    }
+
+    #[test]
+    fn multiple_members() {
+        let mut data = gzip("first\n");
+        data.extend(gzip("second\n"));
+        assert_eq!("first\nsecond\n", read("data/ws.txt.gz", data));
+
+        let mut data = zstd("first\n");
+        data.extend(zstd("second\n"));
+        assert_eq!("first\nsecond\n", read("data/ws.txt.zst", data));
+    }
+
+    #[test]
+    fn zstd_compressed() {
+        assert_eq!(CONTENTS, read("data/ws.txt.zst", zstd(CONTENTS)));
+        assert_eq!(CONTENTS, read("data/ws", zstd(CONTENTS)));
+    }
+}
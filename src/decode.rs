@@ -0,0 +1,349 @@
+/// Decoding of line-delimited messages, with a policy for lines which don't
+/// decode.
+///
+/// Recorded feeds hold more than the messages a reader is after: subscription
+/// acknowledgements, heartbeats, error messages, and often a last line cut
+/// short when the recorder stopped. Rather than each reader deciding what to
+/// do with lines it can't make sense of, they share a `LineDecoder`.
+
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ErrorPolicy {
+    /// Skip lines which don't decode, only counting them.
+    Skip,
+    /// Skip lines which don't decode, keeping their errors.
+    Collect,
+    /// Stop at the first line which doesn't decode.
+    Abort,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ErrorPolicy, String> {
+        match s {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "collect" => Ok(ErrorPolicy::Collect),
+            "abort" => Ok(ErrorPolicy::Abort),
+            _ => Err(format!("Bad error policy {:?}", s)),
+        }
+    }
+}
+
+/// A line which couldn't be read or decoded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineError {
+    /// The file the line is in, when lines come from several files.
+    pub file: Option<Rc<PathBuf>>,
+    /// Counting from 1.
+    pub line: u64,
+    pub message: String,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file {
+            Some(ref file) => write!(f, "{}:{}: {}", file.display(), self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+/// Decodes lines one after another, keeping count of them and applying an
+/// error policy to those which don't decode.
+#[derive(Clone, Debug)]
+pub struct LineDecoder {
+    policy: ErrorPolicy,
+    file: Option<Rc<PathBuf>>,
+    line: u64,
+    skipped: u64,
+    errors: Vec<LineError>,
+}
+
+impl LineDecoder {
+    pub fn new(policy: ErrorPolicy) -> LineDecoder {
+        LineDecoder {
+            policy,
+            file: None,
+            line: 0,
+            skipped: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn policy(&self) -> ErrorPolicy {
+        self.policy
+    }
+
+    /// Decodes the next line with `decode`. If it doesn't decode, returns
+    /// None, or with the abort policy, the error with the line number added.
+    pub fn decode<T, F>(&mut self, line: &str, decode: F) -> io::Result<Option<T>>
+    where
+        F: FnOnce(&str) -> io::Result<T>,
+    {
+        self.line += 1;
+        self.check(decode(line))
+    }
+
+    /// Decodes line number `line` of `file` with `decode`, for lines which
+    /// don't simply follow each other, like those of a replay spanning
+    /// several files. Errors are reported with the file and line.
+    pub fn decode_at<T, F>(
+        &mut self,
+        file: &Rc<PathBuf>,
+        line: u64,
+        text: &str,
+        decode: F,
+    ) -> io::Result<Option<T>>
+    where
+        F: FnOnce(&str) -> io::Result<T>,
+    {
+        self.file = Some(file.clone());
+        self.line = line;
+        self.check(decode(text))
+    }
+
+    /// Applies the policy to a later step of decoding the last line, such
+    /// as interpreting a message once it's been parsed.
+    pub fn check<T>(&mut self, result: io::Result<T>) -> io::Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => self.failed(e).map(|_| None),
+        }
+    }
+
+    /// Applies the policy to an error reading the next line, e.g. because it
+    /// isn't valid UTF-8, or a compressed file was cut short.
+    pub fn read_error(&mut self, e: io::Error) -> io::Result<()> {
+        self.line += 1;
+        self.failed(e)
+    }
+
+    /// Applies the policy to an error reading line number `line` of `file`,
+    /// like `decode_at` for lines which couldn't be read.
+    pub fn read_error_at(&mut self, file: &Rc<PathBuf>, line: u64, e: io::Error) -> io::Result<()> {
+        self.file = Some(file.clone());
+        self.line = line;
+        self.failed(e)
+    }
+
+    fn failed(&mut self, e: io::Error) -> io::Result<()> {
+        let error = LineError {
+            file: self.file.clone(),
+            line: self.line,
+            message: e.to_string(),
+        };
+        match self.policy {
+            ErrorPolicy::Abort => return Err(io::Error::new(e.kind(), error.to_string())),
+            ErrorPolicy::Collect => self.errors.push(error),
+            ErrorPolicy::Skip => {}
+        }
+        self.skipped += 1;
+        Ok(())
+    }
+
+    /// The file of the last line seen, if given with `decode_at`.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref().map(|file| &**file)
+    }
+
+    /// The number of the last line seen, counting from 1.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// The number of lines which didn't decode.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// The errors of the lines which didn't decode, with the collect policy.
+    pub fn errors(&self) -> &[LineError] {
+        &self.errors
+    }
+}
+
+/// An iterator over the lines of a reader, like `BufRead::lines`. A line
+/// which isn't valid UTF-8 is an error of its own, and the lines after it are
+/// still read. Any other error ends the input.
+pub struct Lines<B> {
+    r: B,
+    done: bool,
+}
+
+impl<B: BufRead> Iterator for Lines<B> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        if self.done {
+            return None;
+        }
+        let mut line = Vec::new();
+        match self.r.read_until(b'\n', &mut line) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        Some(String::from_utf8(line).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "Line isn't valid UTF-8")
+        }))
+    }
+}
+
+/// Returns the lines of `r`.
+pub fn lines<B: BufRead>(r: B) -> Lines<B> {
+    Lines { r, done: false }
+}
+
+/// An iterator over the decoded lines of a reader, and their line numbers.
+/// A line which can't be read is handled by the error policy, and unless
+/// it's just not valid UTF-8, ends the input.
+pub struct DecodeLines<B, F> {
+    lines: Lines<B>,
+    decoder: LineDecoder,
+    decode: F,
+    done: bool,
+}
+
+impl<B, F> DecodeLines<B, F> {
+    pub fn decoder(&self) -> &LineDecoder {
+        &self.decoder
+    }
+}
+
+impl<B: BufRead, T, F: FnMut(&str) -> io::Result<T>> Iterator for DecodeLines<B, F> {
+    type Item = io::Result<(u64, T)>;
+
+    fn next(&mut self) -> Option<io::Result<(u64, T)>> {
+        while !self.done {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => {
+                    if let Err(e) = self.decoder.read_error(e) {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+            };
+            match self.decoder.decode(&line, &mut self.decode) {
+                Ok(Some(value)) => return Some(Ok((self.decoder.line(), value))),
+                Ok(None) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Decodes each line of `r` with `decode`, applying `policy` to those which
+/// don't decode.
+pub fn decode_lines<B, T, F>(r: B, policy: ErrorPolicy, decode: F) -> DecodeLines<B, F>
+where
+    B: BufRead,
+    F: FnMut(&str) -> io::Result<T>,
+{
+    DecodeLines {
+        lines: lines(r),
+        decoder: LineDecoder::new(policy),
+        decode,
+        done: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(line: &str) -> io::Result<u64> {
+        line.parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Bad number {:?}", line)))
+    }
+
+    const INPUT: &str = "1\nheartbeat\n3\n4";
+
+    #[test]
+    fn policies() {
+        let mut lines = decode_lines(INPUT.as_bytes(), ErrorPolicy::Skip, number);
+        let values: Vec<(u64, u64)> = lines.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(vec![(1, 1), (3, 3), (4, 4)], values);
+        assert_eq!(1, lines.decoder().skipped());
+        assert!(lines.decoder().errors().is_empty());
+
+        let mut lines = decode_lines(INPUT.as_bytes(), ErrorPolicy::Collect, number);
+        assert_eq!(3, lines.by_ref().count());
+        assert_eq!(
+            vec![LineError {
+                file: None,
+                line: 2,
+                message: "Bad number \"heartbeat\"".to_owned(),
+            }],
+            lines.decoder().errors()
+        );
+
+        let results: Vec<_> = decode_lines(INPUT.as_bytes(), ErrorPolicy::Abort, number).collect();
+        assert_eq!(2, results.len());
+        let err = results[1].as_ref().unwrap_err();
+        assert_eq!("line 2: Bad number \"heartbeat\"", err.to_string());
+    }
+
+    /// Reads `data`, then fails.
+    struct CutShort<'a>(&'a [u8]);
+
+    impl<'a> io::Read for CutShort<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Cut short"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn read_errors() {
+        // A line which isn't UTF-8 is skipped, and the rest still decode.
+        let input: &[u8] = b"1\n\xff\n3\r\n";
+        let mut lines = decode_lines(input, ErrorPolicy::Collect, number);
+        let values: Vec<(u64, u64)> = lines.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(vec![(1, 1), (3, 3)], values);
+        assert_eq!(2, lines.decoder().errors()[0].line);
+        assert_eq!("abort".parse(), Ok(ErrorPolicy::Abort));
+
+        // Any other error ends the input.
+        let r = io::BufReader::new(CutShort(b"1\n2"));
+        let mut lines = decode_lines(r, ErrorPolicy::Skip, number);
+        assert_eq!(1, lines.by_ref().count());
+        assert_eq!(1, lines.decoder().skipped());
+    }
+
+    #[test]
+    fn lines_of_files() {
+        let (a, b) = (Rc::new(PathBuf::from("a.txt")), Rc::new(PathBuf::from("b.txt")));
+        let mut decoder = LineDecoder::new(ErrorPolicy::Collect);
+        assert_eq!(Some(7), decoder.decode_at(&a, 12, "7", number).unwrap());
+        assert_eq!(None, decoder.decode_at(&b, 3, "x", number).unwrap());
+        assert_eq!(Some(&PathBuf::from("b.txt")), decoder.file());
+        assert_eq!("b.txt:3: Bad number \"x\"", decoder.errors()[0].to_string());
+
+        let mut decoder = LineDecoder::new(ErrorPolicy::Abort);
+        let err = decoder.decode_at(&a, 12, "x", number).unwrap_err();
+        assert_eq!("a.txt:12: Bad number \"x\"", err.to_string());
+    }
+}
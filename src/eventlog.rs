@@ -0,0 +1,611 @@
+/// A compact binary log of level 3 events, which is much faster to replay than
+/// the feed's JSON.
+///
+/// A log starts with the magic bytes `CVL3` and a version byte. Then come
+/// records, each introduced by a tag byte:
+///
+/// * `DEFINE_PRODUCT` and `DEFINE_ORDER` are followed by a length-prefixed
+///   string, which gets the next product or order id. An order's id is freed
+///   by its done event, and reused by a later definition (the most recently
+///   freed first), so the table of orders only holds live ones.
+/// * An event tag is followed by the product id, the sequence number and time
+///   as deltas from the product's previous event, the order id(s), and the
+///   rest of the event's fields.
+///
+/// Integers are LEB128 varints, signed ones zigzag encoded. Prices and sizes
+/// are the fixed-point value of `Price`, and times are in nanoseconds.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufReader, Read, Write};
+
+use book::{ChangeEvent, DoneEvent, DoneReason, Level3Event, Level3FeedListener, MatchEvent,
+           NewOrderEvent, OpenEvent, OrderPrice, Sequence, Side, Timestamp};
+use price::Price;
+
+pub const VERSION: u8 = 1;
+const MAGIC: &[u8] = b"CVL3";
+
+const DEFINE_PRODUCT: u8 = 0x01;
+const DEFINE_ORDER: u8 = 0x02;
+const ADD: u8 = 0x10;
+const OPEN: u8 = 0x11;
+const MATCH: u8 = 0x12;
+const CHANGE: u8 = 0x13;
+const DONE: u8 = 0x14;
+
+// Bits of the flags byte of add and change events.
+const BID: u8 = 0x01;
+const MARKET: u8 = 0x02;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn write_varint<W: Write>(w: &mut W, mut v: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    w.write_all(&buf[..len])
+}
+
+fn write_price<W: Write>(w: &mut W, px: Price) -> io::Result<()> {
+    write_varint(w, zigzag(px.to_raw()))
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_varint(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_u8(r)?;
+        if shift > 63 {
+            return Err(invalid("Varint is too long".to_owned()));
+        }
+        v |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+        shift += 7;
+    }
+}
+
+fn read_price<R: Read>(r: &mut R) -> io::Result<Price> {
+    Ok(Price::from_raw(unzigzag(read_varint(r)?)))
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_varint(r)?;
+    let mut buf = Vec::new();
+    r.take(len).read_to_end(&mut buf)?;
+    if buf.len() as u64 != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Truncated string".to_owned(),
+        ));
+    }
+    String::from_utf8(buf).map_err(|e| invalid(format!("Bad string: {}", e)))
+}
+
+fn side_flag(side: Side) -> u8 {
+    match side {
+        Side::Bid => BID,
+        Side::Ask => 0,
+    }
+}
+
+fn flag_side(flags: u8) -> Side {
+    if flags & BID != 0 {
+        Side::Bid
+    } else {
+        Side::Ask
+    }
+}
+
+/// The sequence number and time of a product's previous event, which those of
+/// its next event are relative to.
+#[derive(Copy, Clone, Default)]
+struct Last {
+    seq: u64,
+    time: u64,
+}
+
+impl Last {
+    fn advance(&mut self, seq: i64, time: i64) -> (Sequence, Timestamp) {
+        self.seq = (self.seq as i64 + seq) as u64;
+        self.time = (self.time as i64 + time) as u64;
+        (Sequence::from(self.seq), Timestamp::from_nanos(self.time))
+    }
+}
+
+pub struct EventLogWriter<W: Write> {
+    w: W,
+    products: HashMap<String, u64>,
+    last: Vec<Last>,
+    orders: HashMap<String, u64>,
+    order_count: u64,
+    free_orders: Vec<u64>,
+}
+
+impl<W: Write> EventLogWriter<W> {
+    /// Starts a log, writing its header to `w`.
+    pub fn new(mut w: W) -> io::Result<EventLogWriter<W>> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        Ok(EventLogWriter {
+            w,
+            products: HashMap::new(),
+            last: Vec::new(),
+            orders: HashMap::new(),
+            order_count: 0,
+            free_orders: Vec::new(),
+        })
+    }
+
+    fn product_id(&mut self, product: &str) -> io::Result<u64> {
+        if let Some(&id) = self.products.get(product) {
+            return Ok(id);
+        }
+        self.w.write_all(&[DEFINE_PRODUCT])?;
+        write_str(&mut self.w, product)?;
+        let id = self.last.len() as u64;
+        self.products.insert(product.to_owned(), id);
+        self.last.push(Last::default());
+        Ok(id)
+    }
+
+    fn order_id(&mut self, order_id: &str) -> io::Result<u64> {
+        if let Some(&id) = self.orders.get(order_id) {
+            return Ok(id);
+        }
+        self.w.write_all(&[DEFINE_ORDER])?;
+        write_str(&mut self.w, order_id)?;
+        let id = match self.free_orders.pop() {
+            Some(id) => id,
+            None => {
+                self.order_count += 1;
+                self.order_count - 1
+            }
+        };
+        self.orders.insert(order_id.to_owned(), id);
+        Ok(id)
+    }
+
+    pub fn write_event(&mut self, product: &str, event: &Level3Event) -> io::Result<()> {
+        let product = self.product_id(product)?;
+        let (tag, order, taker) = match *event {
+            Level3Event::Add(ref e) => (ADD, self.order_id(e.order_id())?, None),
+            Level3Event::Open(ref e) => (OPEN, self.order_id(e.order_id())?, None),
+            Level3Event::Match(ref e) => (
+                MATCH,
+                self.order_id(e.maker_order_id())?,
+                Some(self.order_id(e.taker_order_id())?),
+            ),
+            Level3Event::Change(ref e) => (CHANGE, self.order_id(e.order_id())?, None),
+            Level3Event::Done(ref e) => (DONE, self.order_id(e.order_id())?, None),
+        };
+
+        let (seq, time) = (u64::from(event.seq()), event.time().nanos());
+        let last = self.last[product as usize];
+        self.last[product as usize] = Last { seq, time };
+
+        let w = &mut self.w;
+        w.write_all(&[tag])?;
+        write_varint(w, product)?;
+        write_varint(w, zigzag(seq as i64 - last.seq as i64))?;
+        write_varint(w, zigzag(time as i64 - last.time as i64))?;
+        write_varint(w, order)?;
+        if let Some(taker) = taker {
+            write_varint(w, taker)?;
+        }
+        match *event {
+            Level3Event::Add(ref e) => {
+                let price = e.price();
+                let market = if price == OrderPrice::Market { MARKET } else { 0 };
+                w.write_all(&[side_flag(e.side()) | market])?;
+                if let OrderPrice::Limit(px) = price {
+                    write_price(w, px)?;
+                }
+                write_price(w, e.size())?;
+            }
+            Level3Event::Open(ref e) => write_price(w, e.remaining_size())?,
+            Level3Event::Match(ref e) => {
+                w.write_all(&[side_flag(e.side())])?;
+                write_price(w, e.price())?;
+                write_price(w, e.size())?;
+            }
+            Level3Event::Change(ref e) => {
+                match e.price() {
+                    OrderPrice::Market => w.write_all(&[MARKET])?,
+                    OrderPrice::Limit(px) => {
+                        w.write_all(&[0])?;
+                        write_price(w, px)?;
+                    }
+                }
+                write_price(w, e.old_size_or_funds())?;
+                write_price(w, e.new_size_or_funds())?;
+            }
+            Level3Event::Done(ref e) => {
+                let reason = match e.reason() {
+                    DoneReason::Filled => 0,
+                    DoneReason::Canceled => 1,
+                };
+                w.write_all(&[reason])?;
+                self.orders.remove(e.order_id());
+                self.free_orders.push(order);
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the log and returns the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+pub struct EventLogReader<R: Read> {
+    r: BufReader<R>,
+    products: Vec<String>,
+    last: Vec<Last>,
+    orders: Vec<String>,
+    free_orders: Vec<u64>,
+    /// The order freed by the last event read. It's only freed once the next
+    /// event is read, since the last one still refers to it.
+    freed: Option<u64>,
+}
+
+impl<R: Read> EventLogReader<R> {
+    /// Reads the header of a log, failing if it isn't a log of a supported
+    /// version.
+    pub fn new(r: R) -> io::Result<EventLogReader<R>> {
+        let mut r = BufReader::new(r);
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("Not an event log".to_owned()));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(format!("Unsupported event log version {}", header[4])));
+        }
+        Ok(EventLogReader {
+            r,
+            products: Vec::new(),
+            last: Vec::new(),
+            orders: Vec::new(),
+            free_orders: Vec::new(),
+            freed: None,
+        })
+    }
+
+    fn read_tag(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0u8; 1];
+        loop {
+            match self.r.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buf[0])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn read_order_id(&mut self) -> io::Result<usize> {
+        let id = read_varint(&mut self.r)? as usize;
+        if id >= self.orders.len() {
+            return Err(invalid(format!("Undefined order {}", id)));
+        }
+        Ok(id)
+    }
+
+    /// Reads the next event, along with the product it's for. Returns None at
+    /// the end of the log.
+    pub fn read_event(&mut self) -> io::Result<Option<(&str, Level3Event)>> {
+        if let Some(id) = self.freed.take() {
+            self.free_orders.push(id);
+        }
+        let tag = loop {
+            let tag = match self.read_tag()? {
+                Some(tag) => tag,
+                None => return Ok(None),
+            };
+            match tag {
+                DEFINE_PRODUCT => {
+                    let product = read_string(&mut self.r)?;
+                    self.products.push(product);
+                    self.last.push(Last::default());
+                }
+                DEFINE_ORDER => {
+                    let order_id = read_string(&mut self.r)?;
+                    match self.free_orders.pop() {
+                        Some(id) => self.orders[id as usize] = order_id,
+                        None => self.orders.push(order_id),
+                    }
+                }
+                ADD | OPEN | MATCH | CHANGE | DONE => break tag,
+                _ => return Err(invalid(format!("Bad record tag {:#x}", tag))),
+            }
+        };
+
+        let product = read_varint(&mut self.r)? as usize;
+        if product >= self.products.len() {
+            return Err(invalid(format!("Undefined product {}", product)));
+        }
+        let seq_delta = unzigzag(read_varint(&mut self.r)?);
+        let time_delta = unzigzag(read_varint(&mut self.r)?);
+        let (seq, time) = self.last[product].advance(seq_delta, time_delta);
+        let order = self.read_order_id()?;
+
+        let event = match tag {
+            ADD => {
+                let flags = read_u8(&mut self.r)?;
+                let price = if flags & MARKET != 0 {
+                    OrderPrice::Market
+                } else {
+                    OrderPrice::Limit(read_price(&mut self.r)?)
+                };
+                let size = read_price(&mut self.r)?;
+                Level3Event::Add(NewOrderEvent::new(
+                    seq,
+                    time,
+                    &self.orders[order],
+                    flag_side(flags),
+                    price,
+                    size,
+                ))
+            }
+            OPEN => {
+                let remaining_size = read_price(&mut self.r)?;
+                Level3Event::Open(OpenEvent::new(seq, time, &self.orders[order], remaining_size))
+            }
+            MATCH => {
+                let taker = self.read_order_id()?;
+                let side = flag_side(read_u8(&mut self.r)?);
+                let price = read_price(&mut self.r)?;
+                let size = read_price(&mut self.r)?;
+                Level3Event::Match(MatchEvent::new(
+                    seq,
+                    time,
+                    &self.orders[order],
+                    &self.orders[taker],
+                    side,
+                    price,
+                    size,
+                ))
+            }
+            CHANGE => {
+                let price = if read_u8(&mut self.r)? & MARKET != 0 {
+                    OrderPrice::Market
+                } else {
+                    OrderPrice::Limit(read_price(&mut self.r)?)
+                };
+                let old = read_price(&mut self.r)?;
+                let new = read_price(&mut self.r)?;
+                Level3Event::Change(ChangeEvent::new(
+                    seq,
+                    time,
+                    &self.orders[order],
+                    price,
+                    old,
+                    new,
+                ))
+            }
+            _ => {
+                let reason = match read_u8(&mut self.r)? {
+                    0 => DoneReason::Filled,
+                    1 => DoneReason::Canceled,
+                    reason => return Err(invalid(format!("Bad done reason {}", reason))),
+                };
+                self.freed = Some(order as u64);
+                Level3Event::Done(DoneEvent::new(seq, time, &self.orders[order], reason))
+            }
+        };
+        Ok(Some((&self.products[product], event)))
+    }
+
+    /// Dispatches the events for `product` in the rest of the log to
+    /// `listener`. Returns the number of events dispatched.
+    pub fn replay<L: Level3FeedListener + ?Sized>(
+        &mut self,
+        product: &str,
+        listener: &mut L,
+    ) -> io::Result<u64> {
+        let mut count = 0;
+        while let Some((p, event)) = self.read_event()? {
+            if p == product {
+                event.dispatch(listener);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use book::Book;
+
+    fn px(p: f64) -> Price {
+        Price::from(p)
+    }
+
+    fn ts(nanos: u64) -> Timestamp {
+        Timestamp::from_nanos(1_519_578_000_000_000_000 + nanos)
+    }
+
+    fn seq(s: u64) -> Sequence {
+        Sequence::from(s)
+    }
+
+    fn events() -> Vec<(&'static str, Level3Event<'static>)> {
+        vec![
+            (
+                "BTC-USD",
+                Level3Event::Add(NewOrderEvent::new(
+                    seq(100),
+                    ts(0),
+                    "b1",
+                    Side::Bid,
+                    OrderPrice::Limit(px(10.)),
+                    px(1.5),
+                )),
+            ),
+            ("BTC-USD", Level3Event::Open(OpenEvent::new(seq(101), ts(10), "b1", px(1.5)))),
+            (
+                "ETH-USD",
+                Level3Event::Add(NewOrderEvent::new(
+                    seq(7),
+                    ts(5),
+                    "e1",
+                    Side::Ask,
+                    OrderPrice::Market,
+                    px(20.),
+                )),
+            ),
+            (
+                "BTC-USD",
+                Level3Event::Add(NewOrderEvent::new(
+                    seq(102),
+                    ts(20),
+                    "t1",
+                    Side::Ask,
+                    OrderPrice::Limit(px(9.5)),
+                    px(0.5),
+                )),
+            ),
+            (
+                "BTC-USD",
+                Level3Event::Match(MatchEvent::new(
+                    seq(103),
+                    ts(20),
+                    "b1",
+                    "t1",
+                    Side::Bid,
+                    px(10.),
+                    px(0.5),
+                )),
+            ),
+            (
+                "BTC-USD",
+                Level3Event::Done(DoneEvent::new(seq(104), ts(20), "t1", DoneReason::Filled)),
+            ),
+            (
+                "BTC-USD",
+                Level3Event::Change(ChangeEvent::new(
+                    seq(106),
+                    ts(15),
+                    "b1",
+                    OrderPrice::Limit(px(10.)),
+                    px(1.),
+                    px(0.25),
+                )),
+            ),
+            (
+                "ETH-USD",
+                Level3Event::Change(ChangeEvent::new(
+                    seq(8),
+                    ts(30),
+                    "e1",
+                    OrderPrice::Market,
+                    px(20.),
+                    px(12.),
+                )),
+            ),
+            // Reuses the id freed by t1.
+            (
+                "BTC-USD",
+                Level3Event::Add(NewOrderEvent::new(
+                    seq(107),
+                    ts(40),
+                    "a1",
+                    Side::Ask,
+                    OrderPrice::Limit(px(10.5)),
+                    px(3.),
+                )),
+            ),
+            ("BTC-USD", Level3Event::Open(OpenEvent::new(seq(108), ts(40), "a1", px(3.)))),
+            (
+                "BTC-USD",
+                Level3Event::Done(DoneEvent::new(seq(109), ts(50), "b1", DoneReason::Canceled)),
+            ),
+        ]
+    }
+
+    fn log() -> Vec<u8> {
+        let mut writer = EventLogWriter::new(Vec::new()).unwrap();
+        for &(product, ref event) in &events() {
+            writer.write_event(product, event).unwrap();
+        }
+        writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let log = log();
+        let mut reader = EventLogReader::new(&log[..]).unwrap();
+        for (product, event) in events() {
+            let (p, e) = reader.read_event().unwrap().unwrap();
+            assert_eq!(product, p);
+            assert_eq!(event, e);
+        }
+        assert!(reader.read_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn replay_into_book() {
+        let log = log();
+        let mut book = Book::new();
+        let mut reader = EventLogReader::new(&log[..]).unwrap();
+        assert_eq!(9, reader.replay("BTC-USD", &mut book).unwrap());
+        assert!(book.best_bid().is_none());
+        assert_eq!(px(10.5), book.best_ask().unwrap().price);
+        assert_eq!(px(3.), book.best_ask().unwrap().size);
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(EventLogReader::new(&b"CVL4\x01"[..]).is_err());
+        let err = EventLogReader::new(&b"CVL3\x63"[..]).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let mut log = log();
+        log.truncate(log.len() - 1);
+        let mut reader = EventLogReader::new(&log[..]).unwrap();
+        let mut result = Ok(None);
+        for _ in 0..events().len() {
+            result = reader.read_event().map(|e| e.map(|_| ()));
+        }
+        assert_eq!(io::ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+    }
+}
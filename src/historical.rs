@@ -1,147 +1,1623 @@
-pub mod feed {
-    use std::collections::{hash_map, HashMap};
-    use std::io;
-    use std::io::{BufRead, BufReader, Read};
+/// Backends holding historical data: a directory, a tar archive, or memory.
+pub mod storage {
+    use std::collections::HashMap;
+    use std::fs;
     use std::fs::File;
+    use std::io;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
     use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use std::vec;
 
-    use chrono::{DateTime, Utc};
-    use flate2::read::GzDecoder;
-    use futures::{future, stream, Future, Stream};
     use glob;
-    //use glob::glob;
+    use tar;
 
-    trait Glob {
-        //type IterItem: BorrowMut<glob::GlobResult>;
+    pub trait Glob {
         type Paths: Iterator<Item = glob::GlobResult>;
-        fn glob(pattern: &str) -> Result<Self::Paths, glob::PatternError>;
+        fn glob(&self, pattern: &str) -> Result<Self::Paths, glob::PatternError>;
     }
 
-    struct DefaultGlob;
-    impl Glob for DefaultGlob {
-        //type IterItem = glob::GlobResult;
-        type Paths = glob::Paths;
-        fn glob(pattern: &str) -> Result<glob::Paths, glob::PatternError> {
-            glob::glob(&pattern)
-        }
+    pub trait Open {
+        type F: Read + 'static;
+        fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::F>;
+        /// The size of the file at `path`, in bytes.
+        fn size<P: AsRef<Path>>(&self, path: P) -> io::Result<u64>;
     }
 
-    trait Open {
-        type F: Read;
-        fn open<P: AsRef<Path>>(path: P) -> io::Result<Self::F>;
+    /// A backend holding historical data files. The paths used with it are
+    /// those of a `DataStore`, i.e. its templates joined onto its root.
+    ///
+    /// Storage is cloned into the streams reading from it, so it should be
+    /// cheap to clone.
+    pub trait Storage: Open + Glob + Clone + 'static {}
+    impl<T: Open + Glob + Clone + 'static> Storage for T {}
+
+    /// Files on the local filesystem.
+    #[derive(Copy, Clone, Debug, Default)]
+    pub struct Directory;
+
+    impl Glob for Directory {
+        type Paths = glob::Paths;
+        fn glob(&self, pattern: &str) -> Result<glob::Paths, glob::PatternError> {
+            glob::glob(pattern)
+        }
     }
 
-    struct DefaultOpen;
-    impl Open for DefaultOpen {
+    impl Open for Directory {
         type F = File;
-        fn open<P: AsRef<Path>>(path: P) -> io::Result<Self::F> {
+        fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
             File::open(path)
         }
+        fn size<P: AsRef<Path>>(&self, path: P) -> io::Result<u64> {
+            Ok(fs::metadata(path)?.len())
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        )
+    }
+
+    /// Returns the paths matching a glob pattern, in order.
+    fn glob_paths<'a, I>(
+        paths: I,
+        pattern: &str,
+    ) -> Result<vec::IntoIter<glob::GlobResult>, glob::PatternError>
+    where
+        I: Iterator<Item = &'a PathBuf>,
+    {
+        let pattern = glob::Pattern::new(pattern)?;
+        // Like on the filesystem, wildcards don't match across directories.
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        let mut matches: Vec<PathBuf> = paths
+            .filter(|p| pattern.matches_path_with(p, &options))
+            .cloned()
+            .collect();
+        matches.sort();
+        let results: Vec<glob::GlobResult> = matches.into_iter().map(Ok).collect();
+        Ok(results.into_iter())
+    }
+
+    /// Files held in memory, mostly for tests.
+    #[derive(Clone, Debug, Default)]
+    pub struct Memory {
+        files: Rc<HashMap<PathBuf, Vec<u8>>>,
+    }
+
+    impl Memory {
+        pub fn new() -> Memory {
+            Memory::default()
+        }
+
+        pub fn insert<P: Into<PathBuf>>(&mut self, path: P, contents: Vec<u8>) {
+            Rc::make_mut(&mut self.files).insert(path.into(), contents);
+        }
+    }
+
+    impl Glob for Memory {
+        type Paths = vec::IntoIter<glob::GlobResult>;
+        fn glob(&self, pattern: &str) -> Result<Self::Paths, glob::PatternError> {
+            glob_paths(self.files.keys(), pattern)
+        }
+    }
+
+    impl Open for Memory {
+        type F = Cursor<Vec<u8>>;
+        fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::F> {
+            match self.files.get(path.as_ref()) {
+                Some(contents) => Ok(Cursor::new(contents.clone())),
+                None => Err(not_found(path.as_ref())),
+            }
+        }
+        fn size<P: AsRef<Path>>(&self, path: P) -> io::Result<u64> {
+            match self.files.get(path.as_ref()) {
+                Some(contents) => Ok(contents.len() as u64),
+                None => Err(not_found(path.as_ref())),
+            }
+        }
+    }
+
+    /// Files inside an uncompressed tar archive. The archive is indexed once,
+    /// when created, so files can be opened without scanning it again.
+    #[derive(Clone, Debug)]
+    pub struct TarArchive {
+        path: PathBuf,
+        /// The offset and size of each file in the archive.
+        entries: Rc<HashMap<PathBuf, (u64, u64)>>,
+    }
+
+    impl TarArchive {
+        pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<TarArchive> {
+            let path = path.into();
+            let mut entries = HashMap::new();
+            let mut archive = tar::Archive::new(File::open(&path)?);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.into_owned();
+                let name = name.strip_prefix(".").unwrap_or(&name).to_path_buf();
+                entries.insert(name, (entry.raw_file_position(), entry.header().size()?));
+            }
+            Ok(TarArchive {
+                path,
+                entries: Rc::new(entries),
+            })
+        }
+    }
+
+    impl Glob for TarArchive {
+        type Paths = vec::IntoIter<glob::GlobResult>;
+        fn glob(&self, pattern: &str) -> Result<Self::Paths, glob::PatternError> {
+            glob_paths(self.entries.keys(), pattern)
+        }
+    }
+
+    impl Open for TarArchive {
+        type F = io::Take<File>;
+        fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Self::F> {
+            let (offset, size) = match self.entries.get(path.as_ref()) {
+                Some(&entry) => entry,
+                None => return Err(not_found(path.as_ref())),
+            };
+            let mut f = File::open(&self.path)?;
+            f.seek(SeekFrom::Start(offset))?;
+            Ok(f.take(size))
+        }
+        fn size<P: AsRef<Path>>(&self, path: P) -> io::Result<u64> {
+            match self.entries.get(path.as_ref()) {
+                Some(&(_, size)) => Ok(size),
+                None => Err(not_found(path.as_ref())),
+            }
+        }
     }
 
-    /// Finds the chunk of websocket messages starting before start_time.
-    /// If found, returns a Stream of messages.
-    pub fn chunk_starting_approx<'a>(
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        use std::env;
+        use std::fs;
+
+        fn read_all<F: Read>(mut f: F) -> String {
+            let mut s = String::new();
+            f.read_to_string(&mut s).unwrap();
+            s
+        }
+
+        fn paths<G: Glob>(storage: &G, pattern: &str) -> Vec<PathBuf> {
+            storage.glob(pattern).unwrap().map(|p| p.unwrap()).collect()
+        }
+
+        #[test]
+        fn memory() {
+            let mut storage = Memory::new();
+            storage.insert("data/b.txt", b"b".to_vec());
+            storage.insert("data/a.txt", b"a".to_vec());
+            storage.insert("other/c.txt", b"c".to_vec());
+
+            assert_eq!(
+                vec![PathBuf::from("data/a.txt"), PathBuf::from("data/b.txt")],
+                paths(&storage, "data/*.txt")
+            );
+            assert_eq!("b", read_all(storage.open("data/b.txt").unwrap()));
+            assert_eq!(1, storage.size("data/b.txt").unwrap());
+            let err = storage.open("data/c.txt").unwrap_err();
+            assert_eq!(io::ErrorKind::NotFound, err.kind());
+        }
+
+        #[test]
+        fn tar_archive() {
+            let path = env::temp_dir().join("cryptoview-storage-test.tar");
+            {
+                let mut builder = tar::Builder::new(File::create(&path).unwrap());
+                for &(name, contents) in &[("data/a.txt", "first file"), ("data/b.txt", "second")] {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_size(contents.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_cksum();
+                    builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+                }
+                builder.finish().unwrap();
+            }
+
+            let storage = TarArchive::new(&path).unwrap();
+            assert_eq!(
+                vec![PathBuf::from("data/a.txt"), PathBuf::from("data/b.txt")],
+                paths(&storage, "data/?.txt")
+            );
+            assert_eq!("second", read_all(storage.open("data/b.txt").unwrap()));
+            assert_eq!("first file", read_all(storage.open("data/a.txt").unwrap()));
+            assert_eq!(10, storage.size("data/a.txt").unwrap());
+            assert!(storage.open("data/c.txt").is_err());
+
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
+
+pub mod feed {
+    use std::cmp;
+    use std::collections::{hash_map, HashMap, HashSet};
+    use std::io;
+    use std::fs;
+    use std::fmt;
+    use std::io::BufReader;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+    use futures::{future, stream, Future, Stream};
+    use serde_json;
+
+    use book::{Book, Level3Event, Level3FeedListener};
+    use coinbase::{BookSnapshot, Message};
+    use compression;
+    use compression::Compression;
+    use decode;
+    use decode::{ErrorPolicy, LineDecoder};
+    use index;
+    use index::ChunkIndex;
+    use sequence::{Sequence, SequenceEvent, SequenceTracker};
+    use timestamp::Timestamp;
+    use super::storage::{Directory, Storage};
+
+    /// Where historical data lives, and how its files are named.
+    ///
+    /// Templates are paths relative to the root, using `strftime` fields. Only
+    /// fixed-width fields (`%Y`, `%m`, `%d`, `%H`, `%M`, `%S`) are supported.
+    /// The snapshot template also contains a `{product}` placeholder, which
+    /// must be followed by a literal separator such as `_` or `/`.
+    #[derive(Clone, Debug)]
+    pub struct DataStore<S = Directory> {
+        storage: S,
+        root: PathBuf,
+        chunk_template: String,
+        snapshot_template: String,
+        checkpoint_template: String,
+        error_policy: ErrorPolicy,
+    }
+
+    impl DataStore<Directory> {
+        /// A store rooted at `root` on the local filesystem, using the default
+        /// file naming.
+        pub fn new<P: Into<PathBuf>>(root: P) -> DataStore<Directory> {
+            DataStore::with_storage(Directory, root)
+        }
+    }
+
+    impl<S: Storage> DataStore<S> {
+        /// A store rooted at `root` within `storage`, using the default file
+        /// naming.
+        pub fn with_storage<P: Into<PathBuf>>(storage: S, root: P) -> DataStore<S> {
+            DataStore {
+                storage,
+                root: root.into(),
+                chunk_template: "ws_%Y%m%d_%H%M%S.txt.gz".to_owned(),
+                snapshot_template: "{product}_%Y%m%d_%H%M%S.json.gz".to_owned(),
+                checkpoint_template: "checkpoints/{product}_%Y%m%d_%H%M%S.json.gz".to_owned(),
+                error_policy: ErrorPolicy::Abort,
+            }
+        }
+
+        /// Sets the template for hourly chunks of websocket messages.
+        pub fn with_chunk_template(mut self, template: &str) -> DataStore<S> {
+            self.chunk_template = template.to_owned();
+            self
+        }
+
+        /// Sets the template for book snapshots.
+        pub fn with_snapshot_template(mut self, template: &str) -> DataStore<S> {
+            self.snapshot_template = template.to_owned();
+            self
+        }
+
+        /// Sets the template for book checkpoints.
+        pub fn with_checkpoint_template(mut self, template: &str) -> DataStore<S> {
+            self.checkpoint_template = template.to_owned();
+            self
+        }
+
+        /// Sets what to do with messages which don't decode while replaying.
+        /// By default, replay stops with an error.
+        pub fn with_error_policy(mut self, policy: ErrorPolicy) -> DataStore<S> {
+            self.error_policy = policy;
+            self
+        }
+
+        pub fn error_policy(&self) -> ErrorPolicy {
+            self.error_policy
+        }
+
+        pub fn storage(&self) -> &S {
+            &self.storage
+        }
+
+        pub fn root(&self) -> &Path {
+            &self.root
+        }
+
+        /// The path of the chunk for the hour starting at `hour`, as named by
+        /// the chunk template.
+        pub fn chunk_path(&self, hour: DateTime<Utc>) -> PathBuf {
+            self.root.join(hour.format(&self.chunk_template).to_string())
+        }
+
+        /// The paths the chunk for `hour` may have, most likely first: the
+        /// template's own, then the same with each other compression
+        /// extension, or none. Chunks are found whatever their compression.
+        pub fn chunk_paths(&self, hour: DateTime<Utc>) -> Vec<PathBuf> {
+            let path = hour.format(&self.chunk_template).to_string();
+            let base = strip_compression_extension(&path);
+            let mut paths = vec![self.root.join(&path)];
+            for extension in CHUNK_EXTENSIONS {
+                let other = format!("{}{}", base, extension);
+                if other != path {
+                    paths.push(self.root.join(other));
+                }
+            }
+            paths
+        }
+
+        /// Parses the hour out of a chunk path, with any compression
+        /// extension.
+        pub fn parse_chunk_path(&self, path: &Path) -> Option<DateTime<Utc>> {
+            let relative = path.strip_prefix(&self.root).ok()?.to_str()?;
+            Utc.datetime_from_str(
+                strip_compression_extension(relative),
+                strip_compression_extension(&self.chunk_template),
+            ).ok()
+        }
+
+        /// The path of the snapshot of `product` taken at `time`.
+        pub fn snapshot_path(&self, product: &str, time: DateTime<Utc>) -> PathBuf {
+            product_path(&self.root, &self.snapshot_template, product, time)
+        }
+
+        /// Parses the product and time out of a snapshot path.
+        pub fn parse_snapshot_path(&self, path: &Path) -> Option<(String, DateTime<Utc>)> {
+            parse_product_path(&self.root, &self.snapshot_template, path)
+        }
+
+        /// The path of the checkpoint of `product`'s book at `time`.
+        pub fn checkpoint_path(&self, product: &str, time: DateTime<Utc>) -> PathBuf {
+            product_path(&self.root, &self.checkpoint_template, product, time)
+        }
+
+        /// Parses the product and time out of a checkpoint path.
+        pub fn parse_checkpoint_path(&self, path: &Path) -> Option<(String, DateTime<Utc>)> {
+            parse_product_path(&self.root, &self.checkpoint_template, path)
+        }
+
+        /// A glob pattern matching every chunk path, whatever its compression
+        /// extension. It matches other files too, like chunk indexes, so
+        /// paths should be checked with `parse_chunk_path`.
+        pub fn chunk_glob(&self) -> String {
+            let template = strip_compression_extension(&self.chunk_template);
+            let glob = self.root.join(wildcard_fields(template));
+            format!("{}*", glob.to_string_lossy())
+        }
+
+        /// A glob pattern matching every snapshot path.
+        pub fn snapshot_glob(&self) -> String {
+            self.root
+                .join(wildcard_fields(&self.snapshot_template))
+                .to_string_lossy()
+                .into_owned()
+        }
+
+        fn checkpoint_glob(&self) -> String {
+            self.root
+                .join(wildcard_fields(&self.checkpoint_template))
+                .to_string_lossy()
+                .into_owned()
+        }
+
+        /// Every snapshot in the store, or only those of `product`, with the
+        /// product of each, in no particular order.
+        pub fn snapshots(&self, product: Option<&str>) -> io::Result<Vec<(String, SnapshotFile)>> {
+            let parse = |path: &Path| self.parse_snapshot_path(path);
+            self.product_files(&self.snapshot_glob(), parse, product)
+        }
+
+        /// Every checkpoint in the store, or only those of `product`.
+        fn checkpoints(&self, product: Option<&str>) -> io::Result<Vec<(String, SnapshotFile)>> {
+            let parse = |path: &Path| self.parse_checkpoint_path(path);
+            self.product_files(&self.checkpoint_glob(), parse, product)
+        }
+
+        fn product_files<F>(
+            &self,
+            pattern: &str,
+            parse: F,
+            product: Option<&str>,
+        ) -> io::Result<Vec<(String, SnapshotFile)>>
+        where
+            F: Fn(&Path) -> Option<(String, DateTime<Utc>)>,
+        {
+            let mut files = Vec::new();
+            for path in self.glob(pattern)? {
+                if let Some((p, time)) = parse(&path) {
+                    if product.map_or(true, |product| p == product) {
+                        files.push((p, SnapshotFile { path, time }));
+                    }
+                }
+            }
+            Ok(files)
+        }
+
+        /// Every path in the store matching `pattern`.
+        fn glob(&self, pattern: &str) -> io::Result<Vec<PathBuf>> {
+            let paths = self.storage.glob(pattern).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Bad glob {:?}: {}", pattern, e),
+                )
+            })?;
+            paths
+                .map(|entry| entry.map_err(|e| io::Error::new(e.error().kind(), e.to_string())))
+                .collect()
+        }
+    }
+
+    /// The extensions a chunk may have after the one in its template, which
+    /// is taken to be uncompressed.
+    const CHUNK_EXTENSIONS: &[&str] = &["", ".gz", ".zst"];
+
+    /// Strips a compression extension, like `.gz`, off a file name.
+    fn strip_compression_extension(name: &str) -> &str {
+        for extension in &[".gz", ".zst", ".zstd"] {
+            if name.ends_with(extension) {
+                return &name[..name.len() - extension.len()];
+            }
+        }
+        name
+    }
+
+    fn product_path(root: &Path, template: &str, product: &str, time: DateTime<Utc>) -> PathBuf {
+        let template = template.replace("{product}", product);
+        root.join(time.format(&template).to_string())
+    }
+
+    /// Parses the product and time out of a path made from a template with a
+    /// `{product}` field.
+    fn parse_product_path(
+        root: &Path,
+        template: &str,
+        path: &Path,
+    ) -> Option<(String, DateTime<Utc>)> {
+        let relative = path.strip_prefix(root).ok()?.to_str()?;
+        let placeholder = template.find("{product}")?;
+        let after = &template[placeholder + "{product}".len()..];
+        let separator = &after[..after.find('%').unwrap_or(after.len())];
+        if separator.is_empty() {
+            return None;
+        }
+
+        // All fields are fixed width, so formatting the part before the
+        // product with any time tells us where the product starts.
+        let before = &template[..placeholder];
+        let start = Utc.timestamp(0, 0).format(before).to_string().len();
+        let len = relative.get(start..)?.find(separator)?;
+        let product = &relative[start..start + len];
+        if product.contains('/') {
+            return None;
+        }
+
+        let template = template.replace("{product}", product);
+        let time = Utc.datetime_from_str(relative, &template).ok()?;
+        Some((product.to_owned(), time))
+    }
+
+    impl Default for DataStore<Directory> {
+        fn default() -> DataStore<Directory> {
+            DataStore::new("data")
+        }
+    }
+
+    /// Replaces the fields of a template with glob wildcards.
+    fn wildcard_fields(template: &str) -> String {
+        template
+            .replace("{product}", "*")
+            .replace("%Y", "????")
+            .replace("%m", "??")
+            .replace("%d", "??")
+            .replace("%H", "??")
+            .replace("%M", "??")
+            .replace("%S", "??")
+    }
+
+    /// Where a message was recorded: the chunk, and the line within it.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct MessageSource {
+        pub chunk: Rc<PathBuf>,
+        /// Counting from 1.
+        pub line: u64,
+    }
+
+    impl fmt::Display for MessageSource {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}:{}", self.chunk.display(), self.line)
+        }
+    }
+
+    /// An item in a stream of websocket messages spanning one or more chunks.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum FeedItem {
+        /// A raw websocket message, and where it was recorded.
+        Message(String, MessageSource),
+        /// The chunk for the hour starting at this time is missing, so the
+        /// stream has a gap here.
+        MissingChunk(DateTime<Utc>),
+        /// A line which couldn't be read, and why. Unless it just wasn't
+        /// valid UTF-8, the rest of its chunk is skipped too, e.g. because
+        /// the chunk was cut short.
+        Unreadable(String, MessageSource),
+    }
+
+    /// Applies `decoder`'s policy to a line which couldn't be read.
+    fn unreadable(decoder: &mut LineDecoder, error: String, source: &MessageSource) -> io::Result<()> {
+        let e = io::Error::new(io::ErrorKind::InvalidData, error);
+        decoder.read_error_at(&source.chunk, source.line, e)
+    }
+
+    pub type FeedStream = Box<Stream<Item = FeedItem, Error = io::Error>>;
+
+    /// Returns a Stream of the messages of the chunk of websocket messages
+    /// starting before start_time, followed by the messages of each
+    /// subsequent hourly chunk.
+    ///
+    /// The stream ends after the chunk containing end_time, or after the last
+    /// chunk available, whichever comes first. Missing chunks, including the
+    /// first, are reported with a `FeedItem::MissingChunk` for each missing
+    /// hour.
+    ///
+    /// If the first chunk has an index, the stream starts at the index's last
+    /// checkpoint before start_time rather than at the start of the chunk.
+    pub fn chunk_starting_approx<S: Storage>(
+        store: &DataStore<S>,
+        start_time: DateTime<Utc>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<FeedStream, io::Error> {
+        chunks_from(store, start_time, None, end_time)
+    }
+
+    /// Like `chunk_starting_approx`, but when `after` gives a product and a
+    /// sequence number, the index may also be used to skip that product's
+    /// messages up to and including it.
+    fn chunks_from<S: Storage>(
+        store: &DataStore<S>,
+        start_time: DateTime<Utc>,
+        after: Option<(&str, Sequence)>,
+        end_time: Option<DateTime<Utc>>,
+    ) -> Result<FeedStream, io::Error> {
+        let first_hour = start_of_hour(start_time);
+        let seek = Seek {
+            time: Timestamp::from(start_time),
+            after,
+        };
+        let first = match open_chunk(store, first_hour, Some(seek)) {
+            Ok(chunk) => chunk,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                Box::new(stream::once(Ok(FeedItem::MissingChunk(first_hour))))
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut last_hour = latest_chunk_hour(store)?.unwrap_or(first_hour);
+        if let Some(end_time) = end_time {
+            last_hour = cmp::min(last_hour, start_of_hour(end_time - Duration::nanoseconds(1)));
+        }
+        let mut hours = Vec::new();
+        let mut hour = first_hour + Duration::hours(1);
+        while hour <= last_hour {
+            hours.push(hour);
+            hour = hour + Duration::hours(1);
+        }
+
+        // Chunks are opened lazily, as the stream reaches them.
+        let store = store.clone();
+        let rest = stream::iter_ok::<_, io::Error>(hours)
+            .map(move |hour| -> FeedStream {
+                match open_chunk(&store, hour, None) {
+                    Ok(chunk) => chunk,
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        Box::new(stream::once(Ok(FeedItem::MissingChunk(hour))))
+                    }
+                    Err(e) => Box::new(stream::once(Err(e))),
+                }
+            })
+            .flatten();
+        Ok(Box::new(first.chain(rest)))
+    }
+
+    /// Where to start reading a chunk which has an index.
+    struct Seek<'a> {
+        /// Messages before this time aren't wanted.
+        time: Timestamp,
+        /// Nor are the messages of this product up to this sequence number.
+        after: Option<(&'a str, Sequence)>,
+    }
+
+    /// Opens the chunk for `hour`, seeking close to `seek` if it has an index.
+    fn open_chunk<S: Storage>(
+        store: &DataStore<S>,
+        hour: DateTime<Utc>,
+        seek: Option<Seek>,
+    ) -> Result<FeedStream, io::Error> {
+        let (path, f) = open_chunk_file(store, hour)?;
+        let checkpoint = seek.and_then(|seek| {
+            let index = read_index(store, &path)?;
+            // Either checkpoint skips only unwanted messages, so take the
+            // later one.
+            let by_sequence = seek
+                .after
+                .and_then(|(product, seq)| index.seek_sequence(product, seq.next()));
+            let checkpoint = index
+                .seek_time(seek.time)
+                .into_iter()
+                .chain(by_sequence)
+                .max_by_key(|c| c.offset)?
+                .clone();
+            Some((index, checkpoint))
+        });
+        let (d, first_line) = match checkpoint {
+            Some((index, checkpoint)) => (index.open_at(f, &checkpoint)?, checkpoint.line),
+            None => (compression::decompress(&path, f)?, 1),
+        };
+        let chunk = Rc::new(path);
+        let lines = decode::lines(BufReader::new(d)).zip(first_line..).map(move |(text, line)| {
+            let source = MessageSource {
+                chunk: chunk.clone(),
+                line,
+            };
+            match text {
+                Ok(text) => FeedItem::Message(text, source),
+                Err(e) => FeedItem::Unreadable(e.to_string(), source),
+            }
+        });
+        Ok(Box::new(stream::iter_ok(lines)))
+    }
+
+    /// Opens the chunk file for `hour`, whichever of its possible paths it
+    /// has.
+    fn open_chunk_file<S: Storage>(
+        store: &DataStore<S>,
+        hour: DateTime<Utc>,
+    ) -> io::Result<(PathBuf, S::F)> {
+        let paths = store.chunk_paths(hour);
+        for path in &paths {
+            match store.storage.open(path) {
+                Ok(f) => return Ok((path.clone(), f)),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No chunk at {}", paths[0].display()),
+        ))
+    }
+
+    fn start_of_hour(time: DateTime<Utc>) -> DateTime<Utc> {
+        Utc.ymd(time.year(), time.month(), time.day())
+            .and_hms(time.hour(), 0, 0)
+    }
+
+    /// Reads the index of the chunk at `path`. A missing, unreadable or stale
+    /// index just means reading the whole chunk, so that's not an error.
+    fn read_index<S: Storage>(store: &DataStore<S>, path: &Path) -> Option<ChunkIndex> {
+        let f = store.storage.open(index::index_path(path)).ok()?;
+        let index = ChunkIndex::read(f).ok()?;
+        if index.is_stale(store.storage.size(path).ok()?) {
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Returns the hour of the latest chunk file available, if any.
+    fn latest_chunk_hour<S: Storage>(store: &DataStore<S>) -> io::Result<Option<DateTime<Utc>>> {
+        Ok(store
+            .glob(&store.chunk_glob())?
+            .iter()
+            .filter_map(|path| store.parse_chunk_path(path))
+            .max())
+    }
+
+    /// Returns a Stream of the websocket messages for `products` with
+    /// `start_time <= time < end_time`, according to each message's own `time`
+    /// field. An empty product set matches every product.
+    ///
+    /// Messages without a time (e.g. subscription acknowledgements) are
+    /// dropped. Missing chunks and unreadable lines within the range are
+    /// still reported.
+    pub fn messages_between<S: Storage>(
+        store: &DataStore<S>,
         start_time: DateTime<Utc>,
-    ) -> Result<Box<Stream<Item = String, Error = io::Error>>, io::Error> {
-        chunk_starting_approx_impl::<DefaultOpen>(start_time)
+        end_time: DateTime<Utc>,
+        products: HashSet<String>,
+    ) -> Result<FeedStream, io::Error> {
+        messages_from(store, start_time, None, end_time, products)
     }
 
-    fn chunk_starting_approx_impl<'a, F: Open + 'static>(
+    /// Like `messages_between`, but passing `after` on to `chunks_from`.
+    /// Messages it lets the stream skip may still be in it.
+    fn messages_from<S: Storage>(
+        store: &DataStore<S>,
         start_time: DateTime<Utc>,
-    ) -> Result<Box<Stream<Item = String, Error = io::Error>>, io::Error> {
-        let filename = start_time
-            .format("data/ws_%Y%m%d_%H0000.txt.gz")
-            .to_string();
-        let r = BufReader::new(GzDecoder::new(F::open(filename)?));
-        let stream = stream::iter_result(r.lines());
+        after: Option<(&str, Sequence)>,
+        end_time: DateTime<Utc>,
+        products: HashSet<String>,
+    ) -> Result<FeedStream, io::Error> {
+        let (start, end) = (Timestamp::from(start_time), Timestamp::from(end_time));
+        let stream = chunks_from(store, start_time, after, Some(end_time))?.filter(
+            move |item| match *item {
+                FeedItem::Message(ref m, _) => {
+                    // Check the time first, since it rejects everything up to
+                    // start_time in the first chunk.
+                    message_time(m).map_or(false, |t| start <= t && t < end)
+                        && (products.is_empty()
+                            || message_product(m).map_or(false, |p| products.contains(&p)))
+                }
+                FeedItem::MissingChunk(_) | FeedItem::Unreadable(..) => true,
+            },
+        );
         Ok(Box::new(stream))
     }
 
-    /// Returns a book snapshot from around start_time.
-    pub fn snapshot_starting_approx(
+    /// The fields of a websocket message needed for filtering.
+    #[derive(Deserialize)]
+    struct MessageHeader {
+        time: Option<String>,
+        product_id: Option<String>,
+        sequence: Option<u64>,
+    }
+
+    /// Extracts a string field from a raw message by scanning for its key,
+    /// without parsing the rest of the message.
+    fn scan_field<'a>(message: &'a str, key: &str) -> Option<&'a str> {
+        let start = message.find(key)? + key.len();
+        let len = message[start..].find('"')?;
+        Some(&message[start..start + len])
+    }
+
+    fn message_time(message: &str) -> Option<Timestamp> {
+        match scan_field(message, "\"time\":\"") {
+            Some(time) => Timestamp::parse(time).ok(),
+            // Not in the compact form Coinbase sends; fall back to parsing it.
+            None => serde_json::from_str::<MessageHeader>(message)
+                .ok()
+                .and_then(|h| h.time)
+                .and_then(|time| Timestamp::parse(&time).ok()),
+        }
+    }
+
+    fn message_product(message: &str) -> Option<String> {
+        match scan_field(message, "\"product_id\":\"") {
+            Some(product) => Some(product.to_owned()),
+            None => serde_json::from_str::<MessageHeader>(message)
+                .ok()
+                .and_then(|h| h.product_id),
+        }
+    }
+
+    /// A book snapshot file, and the time it was taken.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct SnapshotFile {
+        pub path: PathBuf,
+        pub time: DateTime<Utc>,
+    }
+
+    /// Returns the latest book snapshot of each product taken at or before
+    /// start_time, however long before start_time that was.
+    pub fn snapshot_starting_approx<S: Storage>(
+        store: &DataStore<S>,
         start_time: DateTime<Utc>,
-    ) -> Box<Future<Item = HashMap<String, PathBuf>, Error = io::Error>> {
+    ) -> Box<Future<Item = HashMap<String, SnapshotFile>, Error = io::Error>> {
         // TODO: Open snapshots...
-        get_best_snapshot_per_product::<DefaultGlob>(start_time)
+        get_best_snapshot_per_product(store, start_time)
     }
 
-    fn get_best_snapshot_per_product<G: Glob>(
+    fn get_best_snapshot_per_product<S: Storage>(
+        store: &DataStore<S>,
         start_time: DateTime<Utc>,
-    ) -> Box<Future<Item = HashMap<String, PathBuf>, Error = io::Error>> {
-        let pattern = start_time
-            .format("data/???-???_%Y%m%d_%H????.json.gz")
-            .to_string();
+    ) -> Box<Future<Item = HashMap<String, SnapshotFile>, Error = io::Error>> {
+        let snapshots = store
+            .snapshots(None)
+            .map(|snapshots| latest_per_product(snapshots, start_time));
+        Box::new(future::result(snapshots))
+    }
 
-        // Find the lexicographically smallest filename (earliest time) for each product.
+    /// Finds the latest file at or before start_time for each product.
+    fn latest_per_product(
+        files: Vec<(String, SnapshotFile)>,
+        start_time: DateTime<Utc>,
+    ) -> HashMap<String, SnapshotFile> {
         let mut products = HashMap::new();
-        for mut entry in G::glob(&pattern).expect("Bad glob!") {
-            match entry {
-                Ok(path) => {
-                    let product = path.file_name().unwrap().to_str().unwrap()[..7].to_owned();
-                    match products.entry(product) {
-                        hash_map::Entry::Vacant(v) => {
-                            v.insert(path);
-                        }
-                        hash_map::Entry::Occupied(mut o) => {
-                            if path.file_name() < o.get().file_name() {
-                                *o.get_mut() = path;
-                            }
+        for (product, snapshot) in files {
+            if snapshot.time > start_time {
+                continue;
+            }
+            match products.entry(product) {
+                hash_map::Entry::Vacant(v) => {
+                    v.insert(snapshot);
+                }
+                hash_map::Entry::Occupied(mut o) => {
+                    if snapshot.time > o.get().time {
+                        *o.get_mut() = snapshot;
+                    }
+                }
+            }
+        }
+        products
+    }
+
+    /// The latest snapshot or checkpoint of each product at or before `time`,
+    /// and whether it's a checkpoint. Checkpoints win ties.
+    fn get_best_starting_points<S: Storage>(
+        store: &DataStore<S>,
+        time: DateTime<Utc>,
+    ) -> io::Result<HashMap<String, (SnapshotFile, bool)>> {
+        let snapshots = latest_per_product(store.snapshots(None)?, time);
+        let checkpoints = latest_per_product(store.checkpoints(None)?, time);
+
+        let mut best: HashMap<String, (SnapshotFile, bool)> = snapshots
+            .into_iter()
+            .map(|(product, snapshot)| (product, (snapshot, false)))
+            .collect();
+        for (product, checkpoint) in checkpoints {
+            let later = match best.get(&product) {
+                Some(&(ref snapshot, _)) => checkpoint.time >= snapshot.time,
+                None => true,
+            };
+            if later {
+                best.insert(product, (checkpoint, true));
+            }
+        }
+        Ok(best)
+    }
+
+    /// A discontinuity in a replayed stream of messages.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum ReplayGap {
+        /// The chunk for the hour starting at this time is missing.
+        MissingChunk(DateTime<Utc>),
+        /// The messages between these two sequence numbers are missing.
+        Skipped(Sequence, Sequence),
+    }
+
+    /// The book was rebuilt from a later snapshot after a gap.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct Resync {
+        pub gap: ReplayGap,
+        pub snapshot: SnapshotFile,
+        /// The sequence number of the snapshot.
+        pub seq: Sequence,
+    }
+
+    /// Something that happened while replaying messages onto a book, which
+    /// its consumers may want to know about.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub enum ReplayEvent {
+        Gap(ReplayGap),
+        Resync(Resync),
+    }
+
+    /// Finds the first snapshot later than a time which includes every
+    /// message up to a sequence number.
+    type SnapshotFinder =
+        Box<Fn(DateTime<Utc>, Sequence) -> io::Result<Option<(SnapshotFile, BookSnapshot)>>>;
+
+    /// A book reconstructed at a point in time, and how it was reconstructed.
+    pub struct Reconstruction {
+        pub product: String,
+        pub book: Book,
+        /// The snapshot or checkpoint the book was built from, or last rebuilt
+        /// from after a gap.
+        pub snapshot: SnapshotFile,
+        pub from_checkpoint: bool,
+        /// The sequence number of the last message applied, or of the snapshot
+        /// if there were none.
+        pub seq: Sequence,
+        pub events_applied: u64,
+        /// Gaps in the replayed messages.
+        pub gaps: Vec<ReplayGap>,
+        /// Times the book was rebuilt from a snapshot after a gap.
+        pub resyncs: Vec<Resync>,
+        /// False after a gap, until the book is rebuilt from a snapshot. Events
+        /// are still applied meanwhile, but the book is probably wrong.
+        pub valid: bool,
+        decoder: LineDecoder,
+        find_snapshot: SnapshotFinder,
+    }
+
+    impl Reconstruction {
+        /// Loads the book of `product` from a snapshot or checkpoint, ready to
+        /// replay the messages that follow it. After a gap, the book is rebuilt
+        /// from the first snapshot which covers it, up to `until`.
+        fn load<S: Storage>(
+            store: &DataStore<S>,
+            product: &str,
+            snapshot: SnapshotFile,
+            from_checkpoint: bool,
+            until: DateTime<Utc>,
+        ) -> io::Result<Reconstruction> {
+            let contents = read_snapshot(store, &snapshot)?;
+            let mut later: Vec<SnapshotFile> = store
+                .snapshots(Some(product))?
+                .into_iter()
+                .map(|(_, later)| later)
+                .filter(|later| later.time > snapshot.time && later.time <= until)
+                .collect();
+            later.sort_by_key(|later| later.time);
+            let finder_store = store.clone();
+            let find_snapshot =
+                move |after, seq| first_snapshot_including(&finder_store, &later, after, seq);
+            Ok(Reconstruction {
+                product: product.to_owned(),
+                book: contents.to_book(Timestamp::from(snapshot.time))?,
+                snapshot,
+                from_checkpoint,
+                seq: contents.seq(),
+                events_applied: 0,
+                gaps: Vec::new(),
+                resyncs: Vec::new(),
+                valid: true,
+                decoder: LineDecoder::new(store.error_policy()),
+                find_snapshot: Box::new(find_snapshot),
+            })
+        }
+
+        /// Where to start replaying messages from.
+        fn replay_start(&self) -> DateTime<Utc> {
+            replay_start(self.snapshot.time)
+        }
+
+        fn fold_item(mut self, item: FeedItem) -> io::Result<Reconstruction> {
+            self.apply(item)?;
+            Ok(self)
+        }
+
+        /// Applies a message to the book, or notes a missing chunk. Returns
+        /// the gaps found and any resync from a snapshot which followed.
+        ///
+        /// Messages which can't be read or don't decode are handled according
+        /// to the store's error policy.
+        pub fn apply(&mut self, item: FeedItem) -> io::Result<Vec<ReplayEvent>> {
+            match item {
+                FeedItem::Message(line, source) => {
+                    let message = self.decoder
+                        .decode_at(&source.chunk, source.line, &line, Message::parse)?;
+                    let message = match message {
+                        Some(message) => message,
+                        None => return Ok(Vec::new()),
+                    };
+                    match self.decoder.check(message.to_event())? {
+                        Some(event) => self.apply_message(&message, event),
+                        None => Ok(Vec::new()),
+                    }
+                }
+                FeedItem::MissingChunk(hour) => {
+                    // Replay may start in the hour before the snapshot, whose
+                    // messages the snapshot includes anyway.
+                    if hour + Duration::hours(1) <= self.snapshot.time {
+                        return Ok(Vec::new());
+                    }
+                    // The next message will show which sequence numbers are
+                    // missing, and resync then.
+                    let gap = ReplayGap::MissingChunk(hour);
+                    self.gaps.push(gap.clone());
+                    self.valid = false;
+                    Ok(vec![ReplayEvent::Gap(gap)])
+                }
+                // Messages lost with it will show as a gap.
+                FeedItem::Unreadable(error, source) => {
+                    unreadable(&mut self.decoder, error, &source)?;
+                    Ok(Vec::new())
+                }
+            }
+        }
+
+        /// The decoder of the messages replayed. Its errors give the chunk
+        /// and line of each message which didn't decode.
+        pub fn decoder(&self) -> &LineDecoder {
+            &self.decoder
+        }
+
+        fn apply_message(
+            &mut self,
+            message: &Message,
+            event: Option<Level3Event>,
+        ) -> io::Result<Vec<ReplayEvent>> {
+            let event = match event {
+                Some(event) => event,
+                None => return Ok(Vec::new()),
+            };
+
+            let seq = event.seq();
+            if seq <= self.seq {
+                // Already included in the snapshot.
+                return Ok(Vec::new());
+            }
+            let mut events = Vec::new();
+            if !self.seq.is_followed_by(seq) {
+                let gap = ReplayGap::Skipped(self.seq, seq);
+                self.gaps.push(gap.clone());
+                self.valid = false;
+                events.push(ReplayEvent::Gap(gap.clone()));
+
+                // The snapshot must include everything up to this message, so
+                // it can't have been taken before the message just before it,
+                // which was at about the same time as this one.
+                let before = Sequence::from(u64::from(seq) - 1);
+                let after = match message.time {
+                    Some(_) => cmp::max(
+                        self.snapshot.time,
+                        DateTime::<Utc>::from(message.timestamp()?)
+                            - Duration::seconds(SNAPSHOT_SLACK_SECS),
+                    ),
+                    None => self.snapshot.time,
+                };
+                if let Some((snapshot, contents)) = (self.find_snapshot)(after, before)? {
+                    let resync = Resync {
+                        gap,
+                        snapshot: snapshot.clone(),
+                        seq: contents.seq(),
+                    };
+                    let time = Timestamp::from(snapshot.time);
+                    let book = contents.to_book(time)?;
+                    self.book.replace_orders(book, contents.seq(), time);
+                    self.snapshot = snapshot;
+                    self.from_checkpoint = false;
+                    self.seq = contents.seq();
+                    self.valid = true;
+                    self.resyncs.push(resync.clone());
+                    events.push(ReplayEvent::Resync(resync));
+                    if seq <= self.seq {
+                        return Ok(events);
+                    }
+                }
+            }
+            self.seq = seq;
+            replay_event(&mut self.book, message, &event)?;
+            self.events_applied += 1;
+            Ok(events)
+        }
+    }
+
+    fn read_snapshot<S: Storage>(
+        store: &DataStore<S>,
+        snapshot: &SnapshotFile,
+    ) -> io::Result<BookSnapshot> {
+        let f = store.storage.open(&snapshot.path)?;
+        BookSnapshot::read(compression::decompress(&snapshot.path, f)?)
+    }
+
+    /// Reads just the sequence number of a snapshot.
+    fn read_snapshot_sequence<S: Storage>(
+        store: &DataStore<S>,
+        snapshot: &SnapshotFile,
+    ) -> io::Result<Sequence> {
+        let f = store.storage.open(&snapshot.path)?;
+        BookSnapshot::read_sequence(compression::decompress(&snapshot.path, f)?)
+    }
+
+    /// Finds the first of `snapshots`, which are in time order, taken after
+    /// `after` which includes every message up to `seq`. Only the sequence
+    /// numbers of the snapshots before it are read.
+    fn first_snapshot_including<S: Storage>(
+        store: &DataStore<S>,
+        snapshots: &[SnapshotFile],
+        after: DateTime<Utc>,
+        seq: Sequence,
+    ) -> io::Result<Option<(SnapshotFile, BookSnapshot)>> {
+        for snapshot in snapshots.iter().filter(|snapshot| snapshot.time > after) {
+            if read_snapshot_sequence(store, snapshot)? >= seq {
+                let contents = read_snapshot(store, snapshot)?;
+                return Ok(Some((snapshot.clone(), contents)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies an event to a book which was built from a snapshot.
+    ///
+    /// Orders which were received before the snapshot but weren't resting on
+    /// the book at the time aren't in the snapshot, so events for them are
+    /// dropped, or in the case of an open, used to add them.
+    fn replay_event(book: &mut Book, message: &Message, event: &Level3Event) -> io::Result<()> {
+        match *event {
+            Level3Event::Open(ref e) if !book.has_order(e.order_id()) => {
+                book.on_add(&message.to_new_order_for_open()?);
+            }
+            Level3Event::Match(ref e) if !book.has_order(e.maker_order_id()) => return Ok(()),
+            Level3Event::Change(ref e) if !book.has_order(e.order_id()) => return Ok(()),
+            Level3Event::Done(ref e) if !book.has_order(e.order_id()) => return Ok(()),
+            _ => {}
+        }
+        event.dispatch(book);
+        Ok(())
+    }
+
+    /// How long before a snapshot's file time to look for the messages that
+    /// follow it. The file time is when the snapshot was saved, which can be
+    /// a little after the exchange took it.
+    const SNAPSHOT_SLACK_SECS: i64 = 60;
+
+    /// Where to start replaying messages from, after a snapshot saved at
+    /// `time`. This may be in the previous chunk, when the snapshot was saved
+    /// just after the start of an hour.
+    fn replay_start(time: DateTime<Utc>) -> DateTime<Utc> {
+        time - Duration::seconds(SNAPSHOT_SLACK_SECS)
+    }
+
+    /// Reconstructs the book of `product` as of `time`, by loading the latest
+    /// snapshot or checkpoint at or before `time` and replaying the messages
+    /// that follow it, up to and including `time`.
+    pub fn book_at<S: Storage>(
+        store: &DataStore<S>,
+        product: &str,
+        time: DateTime<Utc>,
+    ) -> Box<Future<Item = Reconstruction, Error = io::Error>> {
+        replay_from(store, product, time, time)
+    }
+
+    /// Reconstructs the book of `product` as of `time`, like `book_at`, ready
+    /// to apply the messages that follow it up to `until`. After a gap in
+    /// those, the book is rebuilt from snapshots taken up to `until`.
+    pub fn replay_from<S: Storage>(
+        store: &DataStore<S>,
+        product: &str,
+        time: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Box<Future<Item = Reconstruction, Error = io::Error>> {
+        let store = store.clone();
+        let product = product.to_owned();
+        let fut = future::lazy(move || {
+            let (snapshot, from_checkpoint) =
+                match get_best_starting_points(&store, time)?.remove(&product) {
+                    Some(start) => start,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("No snapshot of {} at or before {}", product, time),
+                        ))
+                    }
+                };
+            let init = Reconstruction::load(&store, &product, snapshot, from_checkpoint, until)?;
+
+            let mut products = HashSet::new();
+            products.insert(product.clone());
+            let messages = messages_from(
+                &store,
+                init.replay_start(),
+                Some((&product, init.seq)),
+                time + Duration::nanoseconds(1),
+                products,
+            )?;
+            Ok(messages.fold(init, Reconstruction::fold_item))
+        }).flatten();
+        Box::new(fut)
+    }
+
+    /// The book of a product at a point in time, to be saved as a checkpoint.
+    pub struct BookCheckpoint {
+        pub product: String,
+        pub time: DateTime<Utc>,
+        /// The orders resting on the book, in the format of an exchange
+        /// snapshot.
+        pub snapshot: BookSnapshot,
+    }
+
+    /// Replays messages for several products at once, taking a checkpoint of
+    /// each product's book at every multiple of an interval.
+    pub struct Checkpointer {
+        interval: Duration,
+        next: DateTime<Utc>,
+        books: HashMap<String, Reconstruction>,
+        decoder: LineDecoder,
+    }
+
+    impl Checkpointer {
+        /// Checkpoints are taken at multiples of `interval` since the epoch,
+        /// from `start` on. The interval must be a whole number of seconds,
+        /// since that's the resolution of file names.
+        pub fn new(interval: Duration, start: DateTime<Utc>) -> Checkpointer {
+            let step = interval.num_seconds();
+            assert!(
+                step > 0 && Duration::seconds(step) == interval,
+                "Checkpoint interval must be a whole number of seconds"
+            );
+            let mut next = start.timestamp() / step * step;
+            if Utc.timestamp(next, 0) < start {
+                next += step;
+            }
+            Checkpointer {
+                interval,
+                next: Utc.timestamp(next, 0),
+                books: HashMap::new(),
+                decoder: LineDecoder::new(ErrorPolicy::Abort),
+            }
+        }
+
+        /// Sets what to do with messages which don't decode. By default, they
+        /// stop the replay with an error.
+        pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Checkpointer {
+            self.decoder = LineDecoder::new(policy);
+            self
+        }
+
+        pub fn decoder(&self) -> &LineDecoder {
+            &self.decoder
+        }
+
+        pub fn add(&mut self, product: &str, reconstruction: Reconstruction) {
+            self.books.insert(product.to_owned(), reconstruction);
+        }
+
+        pub fn books(&self) -> &HashMap<String, Reconstruction> {
+            &self.books
+        }
+
+        /// Applies a message to the book of its product, after taking any
+        /// checkpoints due before it. A checkpoint at time T holds the book as
+        /// of the messages before T.
+        ///
+        /// Books are probably wrong between a gap in their messages and the
+        /// next resync, so they're left out of checkpoints meanwhile.
+        pub fn apply(&mut self, item: FeedItem) -> io::Result<Vec<BookCheckpoint>> {
+            let (line, source) = match item {
+                FeedItem::Message(line, source) => (line, source),
+                FeedItem::MissingChunk(hour) => {
+                    for reconstruction in self.books.values_mut() {
+                        reconstruction.apply(FeedItem::MissingChunk(hour))?;
+                    }
+                    return Ok(Vec::new());
+                }
+                FeedItem::Unreadable(error, source) => {
+                    unreadable(&mut self.decoder, error, &source)?;
+                    return Ok(Vec::new());
+                }
+            };
+            let message = self.decoder
+                .decode_at(&source.chunk, source.line, &line, Message::parse)?;
+            let message = match message {
+                Some(message) => message,
+                None => return Ok(Vec::new()),
+            };
+            let event = match self.decoder.check(message.to_event())? {
+                Some(event) => event,
+                None => return Ok(Vec::new()),
+            };
+
+            let mut checkpoints = Vec::new();
+            if message.time.is_some() {
+                let time = DateTime::<Utc>::from(message.timestamp()?);
+                while self.next <= time {
+                    for (product, reconstruction) in &self.books {
+                        if !reconstruction.valid {
+                            continue;
                         }
+                        checkpoints.push(BookCheckpoint {
+                            product: product.clone(),
+                            time: self.next,
+                            snapshot: BookSnapshot::from_book(
+                                &reconstruction.book,
+                                reconstruction.seq,
+                            ),
+                        });
                     }
+                    self.next = self.next + self.interval;
                 }
-                Err(e) => println!("Error while globbing: {:?}", e),
             }
+
+            let book = message
+                .product_id
+                .as_ref()
+                .and_then(|product| self.books.get_mut(product));
+            if let Some(reconstruction) = book {
+                reconstruction.apply_message(&message, event)?;
+            }
+            Ok(checkpoints)
+        }
+    }
+
+    /// Replays the messages from `start` to `end` of every product with a
+    /// snapshot or checkpoint at or before `start`, and returns a checkpoint
+    /// of each product's book at every multiple of `interval` in between.
+    pub fn checkpoints<S: Storage>(
+        store: &DataStore<S>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        interval: Duration,
+    ) -> Box<Stream<Item = BookCheckpoint, Error = io::Error>> {
+        let store = store.clone();
+        let fut = future::lazy(move || {
+            let mut checkpointer =
+                Checkpointer::new(interval, start).with_error_policy(store.error_policy());
+            let mut replay_start = start;
+            for (product, (snapshot, from_checkpoint)) in get_best_starting_points(&store, start)? {
+                let reconstruction =
+                    Reconstruction::load(&store, &product, snapshot, from_checkpoint, end)?;
+                replay_start = cmp::min(replay_start, reconstruction.replay_start());
+                checkpointer.add(&product, reconstruction);
+            }
+            let products = checkpointer.books().keys().cloned().collect();
+            let messages = messages_between(&store, replay_start, end, products)?;
+            Ok(messages
+                .and_then(move |item| checkpointer.apply(item))
+                .map(stream::iter_ok)
+                .flatten())
+        });
+        Box::new(fut.flatten_stream())
+    }
+
+    /// Writes a checkpoint to its path in a store on the local filesystem.
+    pub fn save_checkpoint(
+        store: &DataStore<Directory>,
+        checkpoint: &BookCheckpoint,
+    ) -> io::Result<()> {
+        let path = store.checkpoint_path(&checkpoint.product, checkpoint.time);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
         }
-        Box::new(future::ok(products))
+        let json = serde_json::to_vec(&checkpoint.snapshot)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let compression = Compression::from_extension(&path).unwrap_or(Compression::None);
+        fs::write(&path, compression::compress(compression, &json)?)
+    }
+
+    /// Whether replay can start from a snapshot, and how far it can go.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct SnapshotCoverage {
+        pub product: String,
+        pub snapshot: SnapshotFile,
+        /// The snapshot's sequence number, or None if it couldn't be read.
+        pub sequence: Option<Sequence>,
+        /// The first sequence number recorded after the snapshot's. Replay can
+        /// start from the snapshot only if it's the very next one.
+        pub next: Option<Sequence>,
+        /// The last sequence number, and its time, before the first gap
+        /// after the snapshot, if replay can start from it.
+        pub contiguous_until: Option<(Sequence, Timestamp)>,
+        /// Whether the snapshot was taken so long before the start of the
+        /// check that the messages after it weren't read.
+        pub too_old: bool,
+    }
+
+    impl SnapshotCoverage {
+        pub fn usable(&self) -> bool {
+            self.contiguous_until.is_some()
+        }
+    }
+
+    /// How long before the start of a coverage check a snapshot may have
+    /// been taken and still be checked. Older ones would mean reading that
+    /// many hours of messages.
+    pub const COVERAGE_MAX_AGE_HOURS: i64 = 24;
+
+    /// A stretch of a product's messages without gaps.
+    struct ContiguousRun {
+        first: Sequence,
+        last: Sequence,
+        last_time: Timestamp,
+    }
+
+    /// Splits each product's messages into runs without gaps. Duplicate and
+    /// late messages don't break a run.
+    #[derive(Default)]
+    struct RunTracker {
+        trackers: HashMap<String, SequenceTracker>,
+        runs: HashMap<String, Vec<ContiguousRun>>,
+    }
+
+    impl RunTracker {
+        fn add(mut self, item: FeedItem) -> io::Result<RunTracker> {
+            let line = match item {
+                FeedItem::Message(line, _) => line,
+                // The next message of each product will show the gap.
+                FeedItem::MissingChunk(_) | FeedItem::Unreadable(..) => return Ok(self),
+            };
+            let header = match serde_json::from_str::<MessageHeader>(&line) {
+                Ok(header) => header,
+                Err(_) => return Ok(self),
+            };
+            let (product, seq, time) = match (header.product_id, header.sequence, header.time) {
+                (Some(product), Some(seq), Some(time)) => (product, Sequence::from(seq), time),
+                _ => return Ok(self),
+            };
+            let time = match Timestamp::parse(&time) {
+                Ok(time) => time,
+                Err(_) => return Ok(self),
+            };
+            let event = self.trackers
+                .entry(product.clone())
+                .or_insert_with(SequenceTracker::new)
+                .update(seq);
+            let runs = self.runs.entry(product).or_insert_with(Vec::new);
+            match event {
+                SequenceEvent::First | SequenceEvent::Skipped(_, _) => runs.push(ContiguousRun {
+                    first: seq,
+                    last: seq,
+                    last_time: time,
+                }),
+                SequenceEvent::Contiguous => {
+                    if let Some(run) = runs.last_mut() {
+                        run.last = seq;
+                        run.last_time = time;
+                    }
+                }
+                _ => {}
+            }
+            Ok(self)
+        }
+
+        /// The coverage of a snapshot by the runs, unless it's older than
+        /// `oldest`, in which case messages weren't read from it.
+        fn coverage(
+            &self,
+            product: &str,
+            snapshot: SnapshotFile,
+            sequence: Option<Sequence>,
+            oldest: DateTime<Utc>,
+        ) -> SnapshotCoverage {
+            let mut coverage = SnapshotCoverage {
+                product: product.to_owned(),
+                too_old: snapshot.time < oldest,
+                snapshot,
+                sequence,
+                next: None,
+                contiguous_until: None,
+            };
+            let seq = match sequence {
+                Some(seq) if !coverage.too_old => seq,
+                _ => return coverage,
+            };
+            let runs = self.runs.get(product).map_or(&[][..], |runs| &runs[..]);
+            if let Some(run) = runs.iter().find(|run| run.last > seq) {
+                if run.first <= seq.next() {
+                    coverage.next = Some(seq.next());
+                    coverage.contiguous_until = Some((run.last, run.last_time));
+                } else {
+                    coverage.next = Some(run.first);
+                }
+            }
+            coverage
+        }
+    }
+
+    /// Checks whether the recorded messages carry on from each snapshot:
+    /// the latest one of each product at or before `start`, and all of those
+    /// taken after it up to `end`. Messages are read up to `end`.
+    ///
+    /// Snapshots taken more than `COVERAGE_MAX_AGE_HOURS` before `start` are
+    /// reported as too old, rather than read from.
+    pub fn snapshot_coverage<S: Storage>(
+        store: &DataStore<S>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Box<Future<Item = Vec<SnapshotCoverage>, Error = io::Error>> {
+        let store = store.clone();
+        let fut = get_best_snapshot_per_product(&store, start).and_then(move |best| {
+            let mut snapshots: Vec<(String, SnapshotFile)> = best.into_iter().collect();
+            let later = match store.snapshots(None) {
+                Ok(later) => later,
+                Err(e) => return Box::new(future::err(e)) as Box<Future<Item = _, Error = _>>,
+            };
+            snapshots.extend(
+                later
+                    .into_iter()
+                    .filter(|&(_, ref snapshot)| snapshot.time > start && snapshot.time <= end),
+            );
+            snapshots.sort_by(|a, b| (&a.0, a.1.time).cmp(&(&b.0, b.1.time)));
+
+            let sequences: Vec<Option<Sequence>> = snapshots
+                .iter()
+                .map(|&(_, ref snapshot)| read_snapshot_sequence(&store, snapshot).ok())
+                .collect();
+            let oldest = start - Duration::hours(COVERAGE_MAX_AGE_HOURS);
+            let first = snapshots
+                .iter()
+                .filter(|&&(_, ref snapshot)| snapshot.time >= oldest)
+                .map(|&(_, ref snapshot)| replay_start(snapshot.time))
+                .min();
+            let products = snapshots.iter().map(|&(ref p, _)| p.clone()).collect();
+            let coverage = move |runs: RunTracker| {
+                snapshots
+                    .into_iter()
+                    .zip(sequences)
+                    .map(|((product, snapshot), seq)| runs.coverage(&product, snapshot, seq, oldest))
+                    .collect()
+            };
+            let first = match first {
+                Some(time) => time,
+                // Every snapshot is too old, if there are any.
+                None => {
+                    return Box::new(future::ok(coverage(RunTracker::default())))
+                        as Box<Future<Item = _, Error = _>>
+                }
+            };
+            let messages = match messages_between(&store, first, end, products) {
+                Ok(messages) => messages,
+                Err(e) => return Box::new(future::err(e)),
+            };
+            Box::new(messages.fold(RunTracker::default(), RunTracker::add).map(coverage))
+        });
+        Box::new(fut)
     }
 
     #[cfg(test)]
     mod test {
         use super::*;
+        use super::super::storage::{Memory, Open};
 
         use std::path::PathBuf;
-        use std::vec;
 
         use chrono::{TimeZone, Utc};
-        use glob;
         use futures::future::FutureResult;
         use tokio::executor::current_thread;
 
         #[test]
         fn get_best_snapshot() {
-            struct TestGlob;
-            impl<'a> Glob for TestGlob {
-                type Paths = vec::IntoIter<Result<PathBuf, glob::GlobError>>;
-                fn glob(pattern: &str) -> Result<Self::Paths, glob::PatternError> {
-                    let files = vec![
-                        "data/BTC-USD_20180225_170132.json.gz",
-                        "data/BTC-USD_20180225_170017.json.gz",
-                        "data/BTC-USD_20170225_170000.json.gz",
-                        "data/BTC-EUR_20180225_170000.json.gz",
-                    ];
-                    let pattern = glob::Pattern::new(pattern)?;
-                    let glob_result: Vec<Result<PathBuf, glob::GlobError>> = files
-                        .into_iter()
-                        .filter(|s| pattern.matches(s))
-                        .map(|s| Result::Ok(PathBuf::from(s)))
-                        .collect();
-                    Result::Ok(glob_result.into_iter())
+            let mut storage = Memory::new();
+            for file in &[
+                "data/BTC-USD_20180225_170132.json.gz",
+                "data/BTC-USD_20180225_170017.json.gz",
+                "data/BTC-USD_20170225_170000.json.gz",
+                "data/BTC-EUR_20180225_170000.json.gz",
+            ] {
+                storage.insert(*file, Vec::new());
+            }
+            let store = DataStore::with_storage(storage, "data");
+            assert_eq!(4, store.snapshots(None).unwrap().len());
+            assert_eq!(
+                vec![(
+                    "BTC-EUR".to_owned(),
+                    SnapshotFile {
+                        path: PathBuf::from("data/BTC-EUR_20180225_170000.json.gz"),
+                        time: Utc.ymd(2018, 2, 25).and_hms(17, 0, 0),
+                    }
+                )],
+                store.snapshots(Some("BTC-EUR")).unwrap()
+            );
+            let bad = DataStore::with_storage(Memory::new(), "data")
+                .with_snapshot_template("[{product}_%Y%m%d_%H%M%S.json.gz");
+            assert_eq!(
+                io::ErrorKind::InvalidInput,
+                bad.snapshots(None).unwrap_err().kind()
+            );
+
+            fn snapshot(path: &str, time: DateTime<Utc>) -> SnapshotFile {
+                SnapshotFile {
+                    path: PathBuf::from(path),
+                    time,
                 }
             }
 
             current_thread::run(|_| {
-                let query = Utc.ymd(2018, 2, 25).and_hms(17, 0, 0);
-                let fut = get_best_snapshot_per_product::<TestGlob>(query)
+                let query = Utc.ymd(2018, 2, 25).and_hms(17, 1, 0);
+                let fut = get_best_snapshot_per_product(&store, query)
                     .and_then(|result| {
                         let mut expected = HashMap::new();
                         expected.insert(
                             "BTC-USD".into(),
-                            PathBuf::from("data/BTC-USD_20180225_170017.json.gz"),
+                            snapshot(
+                                "data/BTC-USD_20180225_170017.json.gz",
+                                Utc.ymd(2018, 2, 25).and_hms(17, 0, 17),
+                            ),
                         );
                         expected.insert(
                             "BTC-EUR".into(),
-                            PathBuf::from("data/BTC-EUR_20180225_170000.json.gz"),
+                            snapshot(
+                                "data/BTC-EUR_20180225_170000.json.gz",
+                                Utc.ymd(2018, 2, 25).and_hms(17, 0, 0),
+                            ),
                         );
                         assert_eq!(expected, result);
                         Ok(())
@@ -152,14 +1628,26 @@ pub mod feed {
                 current_thread::spawn(fut);
             });
 
+            // Snapshots later in the hour than the query are not used, even if
+            // that means going back a year.
             current_thread::run(|_| {
-                let query = Utc.ymd(2017, 2, 25).and_hms(17, 0, 0);
-                let fut = get_best_snapshot_per_product::<TestGlob>(query)
+                let query = Utc.ymd(2018, 2, 25).and_hms(17, 0, 0);
+                let fut = get_best_snapshot_per_product(&store, query)
                     .and_then(|result| {
                         let mut expected = HashMap::new();
                         expected.insert(
                             "BTC-USD".into(),
-                            PathBuf::from("data/BTC-USD_20170225_170000.json.gz"),
+                            snapshot(
+                                "data/BTC-USD_20170225_170000.json.gz",
+                                Utc.ymd(2017, 2, 25).and_hms(17, 0, 0),
+                            ),
+                        );
+                        expected.insert(
+                            "BTC-EUR".into(),
+                            snapshot(
+                                "data/BTC-EUR_20180225_170000.json.gz",
+                                Utc.ymd(2018, 2, 25).and_hms(17, 0, 0),
+                            ),
                         );
                         assert_eq!(expected, result);
                         Ok(())
@@ -170,11 +1658,33 @@ pub mod feed {
                 current_thread::spawn(fut);
             });
 
+            // Snapshots from earlier hours are used when the hour has none.
             current_thread::run(|_| {
                 let query = Utc.ymd(2017, 2, 25).and_hms(18, 0, 0);
-                let fut = get_best_snapshot_per_product::<TestGlob>(query)
+                let fut = get_best_snapshot_per_product(&store, query)
                     .and_then(|result| {
                         let mut expected = HashMap::new();
+                        expected.insert(
+                            "BTC-USD".into(),
+                            snapshot(
+                                "data/BTC-USD_20170225_170000.json.gz",
+                                Utc.ymd(2017, 2, 25).and_hms(17, 0, 0),
+                            ),
+                        );
+                        assert_eq!(expected, result);
+                        Ok(())
+                    })
+                    .or_else(|_| -> FutureResult<(), ()> {
+                        panic!("get_best_snapshot_per_product failed");
+                    });
+                current_thread::spawn(fut);
+            });
+
+            current_thread::run(|_| {
+                let query = Utc.ymd(2017, 2, 25).and_hms(16, 59, 59);
+                let fut = get_best_snapshot_per_product(&store, query)
+                    .and_then(|result| {
+                        let expected = HashMap::new();
                         assert_eq!(expected, result);
                         Ok(())
                     })
@@ -184,5 +1694,636 @@ pub mod feed {
                 current_thread::spawn(fut);
             });
         }
+
+        fn gzip(contents: &str) -> Vec<u8> {
+            compression::compress(Compression::Gzip, contents.as_bytes()).unwrap()
+        }
+
+        /// Serves chunks at 17:00, 18:00 and 20:00 on 2018-02-25, and others
+        /// for the filtering and reconstruction tests.
+        fn test_storage() -> Memory {
+            let mut storage = Memory::new();
+            for &(path, contents) in &[
+                ("data/ws_20180225_170000.txt.gz", "a\nb\n"),
+                ("data/ws_20180225_180000.txt.gz", "c\n"),
+                ("data/ws_20180225_200000.txt.gz", "d\n"),
+                ("data/ws_20180224_100000.txt.gz", concat!(
+                    r#"{"type":"subscriptions","channels":[]}"#, "\n",
+                    r#"{"type":"open","product_id":"BTC-USD","time":"2018-02-24T10:29:59.999999Z"}"#, "\n",
+                    r#"{"type":"open","product_id":"BTC-USD","time":"2018-02-24T10:30:00.000000Z"}"#, "\n",
+                    r#"{"type":"open","product_id":"ETH-USD","time":"2018-02-24T10:30:01.000000Z"}"#, "\n",
+                    r#"{"type": "open", "time": "2018-02-24T10:45:00Z", "product_id": "BTC-EUR"}"#, "\n",
+                )),
+                ("data/ws_20180224_110000.txt.gz", concat!(
+                    r#"{"type":"done","product_id":"BTC-USD","time":"2018-02-24T11:00:00.100000Z"}"#, "\n",
+                    r#"{"type":"done","product_id":"BTC-USD","time":"2018-02-24T11:05:00.000000Z"}"#, "\n",
+                )),
+                ("data/ws_20180223_090000.txt.gz", concat!(
+                    r#"{"type":"done","product_id":"BTC-USD","sequence":99,"order_id":"b0","reason":"canceled","side":"buy","price":"9.00","remaining_size":"1.0","time":"2018-02-23T09:09:59.000000Z"}"#, "\n",
+                    r#"{"type":"received","product_id":"BTC-USD","sequence":101,"order_id":"b2","order_type":"limit","side":"buy","price":"10.05","size":"0.5","time":"2018-02-23T09:10:01.000000Z"}"#, "\n",
+                    r#"{"type":"received","product_id":"ETH-USD","sequence":5,"order_id":"e1","order_type":"limit","side":"buy","price":"1.00","size":"1.0","time":"2018-02-23T09:10:01.000000Z"}"#, "\n",
+                    r#"{"type":"open","product_id":"BTC-USD","sequence":102,"order_id":"b2","side":"buy","price":"10.05","remaining_size":"0.5","time":"2018-02-23T09:10:02.000000Z"}"#, "\n",
+                    r#"{"type":"received","product_id":"BTC-USD","sequence":103,"order_id":"t1","order_type":"limit","side":"sell","price":"10.00","size":"0.7","time":"2018-02-23T09:11:00.000000Z"}"#, "\n",
+                    r#"{"type":"match","product_id":"BTC-USD","sequence":104,"maker_order_id":"b2","taker_order_id":"t1","side":"buy","price":"10.05","size":"0.5","time":"2018-02-23T09:11:00.000000Z"}"#, "\n",
+                    r#"{"type":"match","product_id":"BTC-USD","sequence":105,"maker_order_id":"b1","taker_order_id":"t1","side":"buy","price":"10.00","size":"0.2","time":"2018-02-23T09:11:00.000000Z"}"#, "\n",
+                    r#"{"type":"done","product_id":"BTC-USD","sequence":106,"order_id":"b2","reason":"filled","side":"buy","price":"10.05","remaining_size":"0","time":"2018-02-23T09:11:00.000000Z"}"#, "\n",
+                    r#"{"type":"done","product_id":"BTC-USD","sequence":107,"order_id":"t1","reason":"filled","side":"sell","price":"10.00","remaining_size":"0","time":"2018-02-23T09:11:00.000000Z"}"#, "\n",
+                    r#"{"type":"open","product_id":"BTC-USD","sequence":109,"order_id":"a9","side":"sell","price":"10.20","remaining_size":"3.0","time":"2018-02-23T09:12:00.000000Z"}"#, "\n",
+                    r#"{"type":"done","product_id":"BTC-USD","sequence":110,"order_id":"a1","reason":"canceled","side":"sell","price":"10.10","remaining_size":"2.0","time":"2018-02-23T09:20:00.000000Z"}"#, "\n",
+                )),
+                ("data/BTC-USD_20180223_091000.json.gz",
+                    r#"{"sequence":100,"bids":[["10.00","1.0","b1"]],"asks":[["10.10","2.0","a1"]]}"#,
+                ),
+            ] {
+                storage.insert(path, gzip(contents));
+            }
+            storage
+        }
+
+        fn test_store() -> DataStore<Memory> {
+            DataStore::with_storage(test_storage(), "data")
+        }
+
+        fn read_chunks(
+            start_time: DateTime<Utc>,
+            end_time: Option<DateTime<Utc>>,
+        ) -> io::Result<Vec<FeedItem>> {
+            chunk_starting_approx(&test_store(), start_time, end_time)?
+                .collect()
+                .wait()
+        }
+
+        #[test]
+        fn seek_with_index() {
+            let mut storage = test_storage();
+            let path = "data/ws_20180224_100000.txt.gz";
+            let index = index::build(path, storage.open(path).unwrap(), 0).unwrap();
+            let mut json = Vec::new();
+            index.write(&mut json).unwrap();
+            storage.insert(index::index_path(path), json);
+            let store = DataStore::with_storage(storage, "data");
+
+            // Starts at the last message before the start time, skipping the
+            // ones before it.
+            let start = Utc.ymd(2018, 2, 24).and_hms_micro(10, 30, 0, 500_000);
+            let end = Utc.ymd(2018, 2, 24).and_hms(11, 0, 0);
+            let items = chunk_starting_approx(&store, start, Some(end))
+                .unwrap()
+                .collect()
+                .wait()
+                .unwrap();
+            assert_eq!(3, items.len());
+            match items[0] {
+                FeedItem::Message(ref m, ref source) => {
+                    assert!(m.contains("10:30:00.000000Z"), "{}", m);
+                    assert_eq!("data/ws_20180224_100000.txt.gz:3", source.to_string());
+                }
+                ref item => panic!("Unexpected {:?}", item),
+            }
+
+            // Once the chunk has changed, its index is stale, and ignored.
+            let mut storage = store.storage.clone();
+            let mut contents = storage.open(path).unwrap().into_inner();
+            contents.extend(gzip(""));
+            storage.insert(path, contents);
+            let store = DataStore::with_storage(storage, "data");
+            let items = chunk_starting_approx(&store, start, Some(end))
+                .unwrap()
+                .collect()
+                .wait()
+                .unwrap();
+            match items[0] {
+                FeedItem::Message(_, ref source) => assert_eq!(1, source.line),
+                ref item => panic!("Unexpected {:?}", item),
+            }
+        }
+
+        #[test]
+        fn seek_by_sequence() {
+            let mut storage = test_storage();
+            let path = "data/ws_20180223_090000.txt.gz";
+            let index = index::build(path, storage.open(path).unwrap(), 0).unwrap();
+            let mut json = Vec::new();
+            index.write(&mut json).unwrap();
+            storage.insert(index::index_path(path), json);
+            let store = DataStore::with_storage(storage, "data");
+
+            // The first message after the start is from before the snapshot,
+            // so only its sequence number lets it be skipped.
+            let start = replay_start(Utc.ymd(2018, 2, 23).and_hms(9, 10, 0));
+            let end = Utc.ymd(2018, 2, 23).and_hms(10, 0, 0);
+            let first_line = |after| {
+                let mut products = HashSet::new();
+                products.insert("BTC-USD".to_owned());
+                let messages = messages_from(&store, start, after, end, products).unwrap();
+                match messages.into_future().wait().ok().unwrap().0 {
+                    Some(FeedItem::Message(_, source)) => source.line,
+                    item => panic!("Unexpected {:?}", item),
+                }
+            };
+            assert_eq!(1, first_line(None));
+            assert_eq!(2, first_line(Some(("BTC-USD", Sequence::from(100)))));
+
+            let time = Utc.ymd(2018, 2, 23).and_hms(9, 12, 0);
+            let r = book_at(&store, "BTC-USD", time).wait().unwrap();
+            assert_eq!(Sequence::from(109), r.seq);
+        }
+
+        #[test]
+        fn chunks_across_hours() {
+            let msg = |s: &str, chunk: &str, line| {
+                let source = MessageSource {
+                    chunk: Rc::new(PathBuf::from(chunk)),
+                    line,
+                };
+                FeedItem::Message(s.to_owned(), source)
+            };
+            let a = msg("a", "data/ws_20180225_170000.txt.gz", 1);
+            let b = msg("b", "data/ws_20180225_170000.txt.gz", 2);
+            let c = msg("c", "data/ws_20180225_180000.txt.gz", 1);
+            let start = Utc.ymd(2018, 2, 25).and_hms(17, 30, 0);
+            let all = vec![
+                a.clone(),
+                b.clone(),
+                c.clone(),
+                FeedItem::MissingChunk(Utc.ymd(2018, 2, 25).and_hms(19, 0, 0)),
+                msg("d", "data/ws_20180225_200000.txt.gz", 1),
+            ];
+
+            assert_eq!(all, read_chunks(start, None).unwrap());
+            assert_eq!(
+                all,
+                read_chunks(start, Some(Utc.ymd(2018, 2, 26).and_hms(0, 0, 0))).unwrap()
+            );
+            assert_eq!(
+                vec![a.clone(), b.clone()],
+                read_chunks(start, Some(Utc.ymd(2018, 2, 25).and_hms(18, 0, 0))).unwrap()
+            );
+            assert_eq!(
+                vec![a, b, c],
+                read_chunks(start, Some(Utc.ymd(2018, 2, 25).and_hms(18, 0, 1))).unwrap()
+            );
+
+            // A missing first chunk is reported like any other.
+            let missing = FeedItem::MissingChunk(Utc.ymd(2018, 2, 25).and_hms(16, 0, 0));
+            let mut from_missing = vec![missing];
+            from_missing.extend(all.clone());
+            assert_eq!(
+                from_missing,
+                read_chunks(Utc.ymd(2018, 2, 25).and_hms(16, 30, 0), None).unwrap()
+            );
+            assert_eq!(
+                all[3..].to_vec(),
+                read_chunks(Utc.ymd(2018, 2, 25).and_hms(19, 30, 0), None).unwrap()
+            );
+        }
+
+        #[test]
+        fn messages_between_filters() {
+            let query = |products: &[&str]| -> Vec<String> {
+                messages_between(
+                    &test_store(),
+                    Utc.ymd(2018, 2, 24).and_hms(10, 30, 0),
+                    Utc.ymd(2018, 2, 24).and_hms_micro(11, 0, 0, 100_001),
+                    products.iter().map(|&p| p.to_owned()).collect(),
+                ).unwrap()
+                    .collect()
+                    .wait()
+                    .unwrap()
+                    .into_iter()
+                    .map(|item| match item {
+                        FeedItem::Message(m, _) => message_time(&m).unwrap().to_string(),
+                        item => panic!("Unexpected {:?}", item),
+                    })
+                    .collect()
+            };
+
+            assert_eq!(
+                vec![
+                    "2018-02-24T10:30:00.000000Z",
+                    "2018-02-24T10:30:01.000000Z",
+                    "2018-02-24T10:45:00.000000Z",
+                    "2018-02-24T11:00:00.100000Z",
+                ],
+                query(&[])
+            );
+            assert_eq!(
+                vec!["2018-02-24T10:30:00.000000Z", "2018-02-24T11:00:00.100000Z"],
+                query(&["BTC-USD"])
+            );
+            assert_eq!(vec!["2018-02-24T10:45:00.000000Z"], query(&["BTC-EUR"]));
+        }
+
+        #[test]
+        fn data_store_layout() {
+            let store = DataStore::new("/mnt/archive")
+                .with_chunk_template("%Y/%m/%d/ws_%H%M%S.txt.gz")
+                .with_snapshot_template("%Y%m%d/{product}/%H%M%S.json.gz");
+            let hour = Utc.ymd(2018, 2, 25).and_hms(17, 0, 0);
+            let time = Utc.ymd(2018, 2, 25).and_hms(17, 1, 32);
+
+            let chunk = store.chunk_path(hour);
+            assert_eq!(PathBuf::from("/mnt/archive/2018/02/25/ws_170000.txt.gz"), chunk);
+            assert_eq!(Some(hour), store.parse_chunk_path(&chunk));
+            assert_eq!("/mnt/archive/????/??/??/ws_??????.txt*", store.chunk_glob());
+            for name in &["ws_170000.txt", "ws_170000.txt.zst"] {
+                let path = Path::new("/mnt/archive/2018/02/25").join(name);
+                assert_eq!(Some(hour), store.parse_chunk_path(&path));
+                assert!(store.chunk_paths(hour).contains(&path));
+            }
+            assert_eq!(
+                None,
+                store.parse_chunk_path(Path::new("/mnt/archive/2018/02/25/ws_170000.txt.gz.idx"))
+            );
+
+            let snapshot = store.snapshot_path("BTC-USD", time);
+            assert_eq!(
+                PathBuf::from("/mnt/archive/20180225/BTC-USD/170132.json.gz"),
+                snapshot
+            );
+            assert_eq!(
+                Some(("BTC-USD".to_owned(), time)),
+                store.parse_snapshot_path(&snapshot)
+            );
+            assert_eq!("/mnt/archive/????????/*/??????.json.gz", store.snapshot_glob());
+            assert_eq!(
+                None,
+                store.parse_snapshot_path(Path::new("/elsewhere/20180225/BTC-USD/170132.json.gz"))
+            );
+
+            let default = DataStore::default();
+            assert_eq!(
+                Some(("BTC-EUR".to_owned(), time)),
+                default.parse_snapshot_path(Path::new("data/BTC-EUR_20180225_170132.json.gz"))
+            );
+            assert_eq!(
+                PathBuf::from("data/ws_20180225_170000.txt.gz"),
+                default.chunk_path(hour)
+            );
+        }
+
+        #[test]
+        fn reconstruct_book() {
+            use book::Side;
+            use price::Price;
+
+            let px = |p: f64| Price::from(p);
+            let time = Utc.ymd(2018, 2, 23).and_hms(9, 15, 0);
+            let r = book_at(&test_store(), "BTC-USD", time)
+                .wait()
+                .unwrap();
+
+            assert_eq!(
+                PathBuf::from("data/BTC-USD_20180223_091000.json.gz"),
+                r.snapshot.path
+            );
+            assert_eq!(Sequence::from(109), r.seq);
+            assert_eq!(8, r.events_applied);
+            assert_eq!(
+                vec![ReplayGap::Skipped(Sequence::from(107), Sequence::from(109))],
+                r.gaps
+            );
+            assert!(!r.valid);
+            assert_eq!(px(10.00), r.book.best_bid().unwrap().price);
+            assert_eq!(px(0.8), r.book.best_bid().unwrap().size);
+            assert_eq!(px(10.10), r.book.best_ask().unwrap().price);
+            assert_eq!(
+                px(3.0),
+                r.book.price_level(Side::Ask, px(10.20)).unwrap().open_size()
+            );
+            assert!(!r.book.has_order("t1"));
+
+            let err = book_at(&test_store(), "BTC-EUR", time)
+                .wait()
+                .err()
+                .unwrap();
+            assert_eq!(io::ErrorKind::NotFound, err.kind());
+        }
+
+        #[test]
+        fn replay_from_previous_hour() {
+            // The replay starts in the hour before the snapshot, whose chunk
+            // is missing.
+            let mut storage = test_storage();
+            storage.insert(
+                "data/BTC-USD_20180223_090020.json.gz",
+                gzip(r#"{"sequence":98,"bids":[["9.00","1.0","b0"]],"asks":[]}"#),
+            );
+            let store = DataStore::with_storage(storage, "data");
+            let time = Utc.ymd(2018, 2, 23).and_hms(9, 9, 59);
+            let r = book_at(&store, "BTC-USD", time).wait().unwrap();
+
+            assert!(r.replay_start() < Utc.ymd(2018, 2, 23).and_hms(9, 0, 0));
+            assert_eq!(Sequence::from(99), r.seq);
+            assert_eq!(Vec::<ReplayGap>::new(), r.gaps);
+            assert!(r.valid);
+            assert!(!r.book.has_order("b0"));
+        }
+
+        #[test]
+        fn resync_after_gap() {
+            use std::cell::RefCell;
+
+            use book::{Level1Event, Level1EventListener, Side};
+            use price::Price;
+
+            // Includes the missing 108, which opened a8.
+            let mut storage = test_storage();
+            storage.insert(
+                "data/BTC-USD_20180223_091130.json.gz",
+                gzip(r#"{"sequence":108,"bids":[["10.00","0.8","b1"]],"asks":[["10.10","2.0","a1"],["10.15","1.0","a8"]]}"#),
+            );
+            // Taken well before the gap showed, so it isn't even read.
+            storage.insert("data/BTC-USD_20180223_091045.json.gz", gzip("unreadable"));
+            let store = DataStore::with_storage(storage, "data");
+
+            let start = Utc.ymd(2018, 2, 23).and_hms(9, 10, 30);
+            let end = Utc.ymd(2018, 2, 23).and_hms(9, 30, 0);
+            let (snapshot, _) = get_best_starting_points(&store, start)
+                .unwrap()
+                .remove("BTC-USD")
+                .unwrap();
+            let mut r = Reconstruction::load(&store, "BTC-USD", snapshot, false, end).unwrap();
+            struct Asks(Rc<RefCell<Vec<(Sequence, Option<Price>)>>>);
+            impl Level1EventListener for Asks {
+                fn on_level1_change(&mut self, event: &Level1Event) {
+                    let ask = event.best_ask().map(|ask| ask.price);
+                    self.0.borrow_mut().push((event.seq(), ask));
+                }
+            }
+            let asks = Rc::new(RefCell::new(Vec::new()));
+            r.book.set_level1_listener(Box::new(Asks(asks.clone())));
+            let mut products = HashSet::new();
+            products.insert("BTC-USD".to_owned());
+            let items = messages_between(&store, r.replay_start(), end, products)
+                .unwrap()
+                .collect()
+                .wait()
+                .unwrap();
+            let mut events = Vec::new();
+            for item in items {
+                events.extend(r.apply(item).unwrap());
+            }
+
+            let gap = ReplayGap::Skipped(Sequence::from(107), Sequence::from(109));
+            let resync = Resync {
+                gap: gap.clone(),
+                snapshot: SnapshotFile {
+                    path: PathBuf::from("data/BTC-USD_20180223_091130.json.gz"),
+                    time: Utc.ymd(2018, 2, 23).and_hms(9, 11, 30),
+                },
+                seq: Sequence::from(108),
+            };
+            assert_eq!(vec![ReplayEvent::Gap(gap), ReplayEvent::Resync(resync.clone())], events);
+            assert_eq!(vec![resync], r.resyncs);
+            assert!(r.valid);
+            assert_eq!(Sequence::from(110), r.seq);
+
+            let px = |p: f64| Price::from(p);
+            assert_eq!(px(10.15), r.book.best_ask().unwrap().price);
+            assert_eq!(
+                px(3.0),
+                r.book.price_level(Side::Ask, px(10.20)).unwrap().open_size()
+            );
+            assert!(!r.book.has_order("a1"));
+            // The listener carries on after the resync.
+            assert_eq!(
+                Some(&(Sequence::from(110), Some(px(10.15)))),
+                asks.borrow().last()
+            );
+
+            // Checkpoints carry on after the resync.
+            let checkpoints = checkpoints(&store, start, end, Duration::seconds(60))
+                .collect()
+                .wait()
+                .unwrap();
+            assert_eq!(10, checkpoints.len());
+            let last = checkpoints.last().unwrap();
+            assert_eq!(Utc.ymd(2018, 2, 23).and_hms(9, 20, 0), last.time);
+            assert_eq!(109, last.snapshot.sequence);
+        }
+
+        #[test]
+        fn replay_past_start() {
+            let mut storage = test_storage();
+            storage.insert(
+                "data/BTC-USD_20180223_091130.json.gz",
+                gzip(r#"{"sequence":108,"bids":[["10.00","0.8","b1"]],"asks":[["10.10","2.0","a1"],["10.15","1.0","a8"]]}"#),
+            );
+            let store = DataStore::with_storage(storage, "data");
+            let start = Utc.ymd(2018, 2, 23).and_hms(9, 11, 0);
+            let end = Utc.ymd(2018, 2, 23).and_hms(9, 30, 0);
+            let replay = |r: &mut Reconstruction| {
+                let mut products = HashSet::new();
+                products.insert("BTC-USD".to_owned());
+                let after = start + Duration::nanoseconds(1);
+                let items = messages_between(&store, after, end, products)
+                    .unwrap()
+                    .collect()
+                    .wait()
+                    .unwrap();
+                for item in items {
+                    r.apply(item).unwrap();
+                }
+            };
+
+            // The snapshot after the gap is later than the start, so only a
+            // replay up to the end can resync from it.
+            let mut r = book_at(&store, "BTC-USD", start).wait().unwrap();
+            replay(&mut r);
+            assert!(r.resyncs.is_empty());
+            assert!(!r.valid);
+
+            let mut r = replay_from(&store, "BTC-USD", start, end).wait().unwrap();
+            assert_eq!(Sequence::from(107), r.seq);
+            replay(&mut r);
+            assert_eq!(1, r.resyncs.len());
+            assert_eq!(Sequence::from(108), r.resyncs[0].seq);
+            assert!(r.valid);
+            assert_eq!(Sequence::from(110), r.seq);
+        }
+
+        #[test]
+        fn check_snapshot_coverage() {
+            let mut storage = test_storage();
+            storage.insert(
+                "data/BTC-USD_20180223_091130.json.gz",
+                gzip(r#"{"sequence":108,"bids":[],"asks":[]}"#),
+            );
+            storage.insert(
+                "data/BTC-USD_20180223_091159.json.gz",
+                gzip(r#"{"sequence":111,"bids":[],"asks":[]}"#),
+            );
+            storage.insert("data/BTC-USD_20180223_091500.json.gz", gzip("{"));
+            storage.insert(
+                "data/ETH-USD_20180222_090000.json.gz",
+                gzip(r#"{"sequence":4,"bids":[],"asks":[]}"#),
+            );
+            let store = DataStore::with_storage(storage, "data");
+
+            let start = Utc.ymd(2018, 2, 23).and_hms(9, 10, 30);
+            let end = Utc.ymd(2018, 2, 23).and_hms(9, 30, 0);
+            let coverage = snapshot_coverage(&store, start, end).wait().unwrap();
+            let summary: Vec<_> = coverage
+                .iter()
+                .map(|c| (c.snapshot.time.minute(), c.sequence, c.next, c.usable()))
+                .collect();
+            let seq = |s| Some(Sequence::from(s));
+            assert_eq!(
+                vec![
+                    (10, seq(100), seq(101), true),
+                    (11, seq(108), seq(109), true),
+                    (11, seq(111), None, false),
+                    (15, None, None, false),
+                    (0, seq(4), None, false),
+                ],
+                summary
+            );
+            let too_old: Vec<_> = coverage.iter().map(|c| c.too_old).collect();
+            assert_eq!(vec![false, false, false, false, true], too_old);
+
+            // The first stops at the gap before 109.
+            let (last, time) = coverage[0].contiguous_until.unwrap();
+            assert_eq!(Sequence::from(107), last);
+            assert_eq!(Timestamp::parse("2018-02-23T09:11:00Z").unwrap(), time);
+            assert_eq!(Sequence::from(110), coverage[1].contiguous_until.unwrap().0);
+        }
+
+        #[test]
+        fn bad_messages() {
+            use decode::ErrorPolicy;
+
+            let mut storage = test_storage();
+            let path = "data/ws_20180223_090000.txt.gz";
+            let mut contents = String::new();
+            compression::decompress(path, storage.open(path).unwrap())
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents.push_str(
+                r#"{"type":"open","product_id":"BTC-USD","sequence":111,"time":"2018-02-23T09:14:00.000000Z","order_id":"#,
+            );
+            storage.insert(path, gzip(&contents));
+
+            let time = Utc.ymd(2018, 2, 23).and_hms(9, 15, 0);
+            let store = DataStore::with_storage(storage, "data");
+            let err = book_at(&store, "BTC-USD", time).wait().err().unwrap();
+            // The 12th line of the chunk, after the 11 messages.
+            assert!(
+                err.to_string()
+                    .starts_with("data/ws_20180223_090000.txt.gz:12: Bad message"),
+                "{}",
+                err
+            );
+
+            let store = store.with_error_policy(ErrorPolicy::Collect);
+            let r = book_at(&store, "BTC-USD", time).wait().unwrap();
+            assert_eq!(Sequence::from(109), r.seq);
+            assert_eq!(1, r.decoder().skipped());
+            let error = &r.decoder().errors()[0];
+            assert_eq!(Some(&PathBuf::from(path)), error.file.as_ref().map(|f| &**f));
+            assert_eq!(12, error.line);
+        }
+
+        #[test]
+        fn truncated_chunks() {
+            use decode::ErrorPolicy;
+
+            let mut storage = test_storage();
+            let path = "data/ws_20180223_090000.txt.gz";
+            let mut contents = storage.open(path).unwrap().into_inner();
+            // Without the gzip trailer, which ends with the data's size.
+            let len = contents.len() - 8;
+            contents.truncate(len);
+            storage.insert(path, contents);
+            storage.insert(
+                "data/ws_20180223_100000.txt.gz",
+                gzip(concat!(
+                    r#"{"type":"done","product_id":"BTC-USD","sequence":111,"order_id":"a9","reason":"canceled","side":"sell","price":"10.20","remaining_size":"3.0","time":"2018-02-23T10:05:00.000000Z"}"#,
+                    "\n",
+                )),
+            );
+            let store = DataStore::with_storage(storage, "data");
+
+            let start = Utc.ymd(2018, 2, 23).and_hms(9, 0, 0);
+            let end = Utc.ymd(2018, 2, 23).and_hms(11, 0, 0);
+            let items = messages_between(&store, start, end, HashSet::new())
+                .unwrap()
+                .collect()
+                .wait()
+                .unwrap();
+            assert_eq!(13, items.len());
+            match items[11] {
+                FeedItem::Unreadable(_, ref source) => {
+                    assert_eq!("data/ws_20180223_090000.txt.gz:12", source.to_string())
+                }
+                ref item => panic!("Unexpected {:?}", item),
+            }
+
+            let time = Utc.ymd(2018, 2, 23).and_hms(10, 30, 0);
+            let err = book_at(&store, "BTC-USD", time).wait().err().unwrap();
+            assert!(err.to_string().starts_with("data/ws_20180223_090000.txt.gz:12: "), "{}", err);
+
+            // Other policies carry on with the next chunk.
+            let store = store.with_error_policy(ErrorPolicy::Skip);
+            let r = book_at(&store, "BTC-USD", time).wait().unwrap();
+            assert_eq!(Sequence::from(111), r.seq);
+            assert_eq!(1, r.decoder().skipped());
+        }
+
+        #[test]
+        fn checkpoint_books() {
+            use book::Side;
+            use price::Price;
+
+            let start = Utc.ymd(2018, 2, 23).and_hms(9, 10, 30);
+            let end = Utc.ymd(2018, 2, 23).and_hms(9, 30, 0);
+            let checkpoints = checkpoints(&test_store(), start, end, Duration::seconds(60))
+                .collect()
+                .wait()
+                .unwrap();
+
+            // None are taken after the gap before 109.
+            let times: Vec<_> = checkpoints.iter().map(|c| c.time).collect();
+            assert_eq!(
+                vec![
+                    Utc.ymd(2018, 2, 23).and_hms(9, 11, 0),
+                    Utc.ymd(2018, 2, 23).and_hms(9, 12, 0),
+                ],
+                times
+            );
+            assert_eq!("BTC-USD", checkpoints[0].product);
+            assert_eq!(102, checkpoints[0].snapshot.sequence);
+            assert_eq!(2, checkpoints[0].snapshot.bids.len());
+            assert_eq!(107, checkpoints[1].snapshot.sequence);
+
+            // The checkpoint is preferred to the earlier snapshot.
+            let mut storage = test_storage();
+            let checkpoint = &checkpoints[1];
+            let json = serde_json::to_vec(&checkpoint.snapshot).unwrap();
+            let store = DataStore::with_storage(storage.clone(), "data");
+            storage.insert(
+                store.checkpoint_path(&checkpoint.product, checkpoint.time),
+                compression::compress(Compression::Gzip, &json).unwrap(),
+            );
+            let store = DataStore::with_storage(storage, "data");
+
+            let px = |p: f64| Price::from(p);
+            let time = Utc.ymd(2018, 2, 23).and_hms(9, 15, 0);
+            let r = book_at(&store, "BTC-USD", time).wait().unwrap();
+            assert_eq!(
+                PathBuf::from("data/checkpoints/BTC-USD_20180223_091200.json.gz"),
+                r.snapshot.path
+            );
+            assert!(r.from_checkpoint);
+            assert_eq!(Sequence::from(109), r.seq);
+            assert_eq!(1, r.events_applied);
+            assert_eq!(px(10.00), r.book.best_bid().unwrap().price);
+            assert_eq!(px(0.8), r.book.best_bid().unwrap().size);
+            assert_eq!(
+                px(3.0),
+                r.book.price_level(Side::Ask, px(10.20)).unwrap().open_size()
+            );
+        }
     }
 }
@@ -0,0 +1,491 @@
+/// Sidecar indexes of websocket chunks, for seeking close to a time or
+/// sequence number without decoding everything before it.
+///
+/// The index of a chunk is stored next to it, with `.idx` appended to its
+/// name. It's a list of checkpoints taken at line starts every so many bytes.
+/// Decompression can only restart at the start of a gzip member or zstd frame
+/// though, so each checkpoint also holds the closest such restart point, from
+/// which the remaining bytes up to the checkpoint have to be decoded and
+/// skipped. That's still much cheaper than parsing them.
+///
+/// A chunk compressed as a single member or frame, as recorders usually
+/// write them, only has a restart point at its start, so seeking in it saves
+/// parsing but not decompression. `recompress` splits a chunk into many
+/// members or frames, after which its index lets readers skip most of it.
+///
+/// An index records the size of its chunk, and isn't used once the chunk's
+/// size has changed, e.g. because it was recompressed or appended to.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::bufread::GzDecoder;
+use serde_json;
+use zstd;
+
+use compression;
+use compression::Compression;
+use sequence::Sequence;
+use timestamp::Timestamp;
+
+pub const VERSION: u32 = 2;
+
+/// Bytes of decompressed data between checkpoints, by default.
+pub const DEFAULT_INTERVAL: u64 = 4 << 20;
+
+/// A place where decompression can start afresh: the start of a gzip member or
+/// zstd frame. Any offset of an uncompressed file is one.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RestartPoint {
+    /// Offset in the file as stored.
+    pub compressed_offset: u64,
+    /// The corresponding offset in the decompressed contents.
+    pub offset: u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Offset of the start of a line in the decompressed contents.
+    pub offset: u64,
+    /// The number of that line, counting from 1.
+    pub line: u64,
+    /// The closest restart point at or before `offset`.
+    pub restart: RestartPoint,
+    /// The time of the message at `offset`.
+    pub time: Timestamp,
+    /// The last sequence number of each product before `offset`.
+    pub sequences: BTreeMap<String, Sequence>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChunkIndex {
+    pub version: u32,
+    pub compression: Compression,
+    /// The size of the chunk as stored, when it was indexed.
+    pub chunk_size: u64,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+/// The path of the index of the chunk at `chunk`.
+pub fn index_path<P: AsRef<Path>>(chunk: P) -> PathBuf {
+    let mut path = chunk.as_ref().as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads and discards `n` bytes.
+fn skip<R: Read + ?Sized>(r: &mut R, n: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut r.take(n), &mut io::sink())?;
+    if skipped < n {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Chunk is shorter than its index",
+        ));
+    }
+    Ok(())
+}
+
+impl ChunkIndex {
+    pub fn read<R: Read>(r: R) -> io::Result<ChunkIndex> {
+        let index: ChunkIndex =
+            serde_json::from_reader(r).map_err(|e| invalid(format!("Bad index: {}", e)))?;
+        if index.version != VERSION {
+            return Err(invalid(format!("Unsupported index version {}", index.version)));
+        }
+        Ok(index)
+    }
+
+    pub fn write<W: Write>(&self, w: W) -> io::Result<()> {
+        serde_json::to_writer(w, self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// The last checkpoint before `time`. Reading from there finds every
+    /// message at or after `time`, as long as times don't go backwards within
+    /// the chunk.
+    pub fn seek_time(&self, time: Timestamp) -> Option<&Checkpoint> {
+        self.checkpoints
+            .iter()
+            .take_while(|c| c.time < time)
+            .last()
+    }
+
+    /// Whether the index is out of date, given the size of its chunk as it's
+    /// stored now.
+    pub fn is_stale(&self, chunk_size: u64) -> bool {
+        self.chunk_size != chunk_size
+    }
+
+    /// The last checkpoint before the message of `product` with sequence
+    /// number `seq`.
+    pub fn seek_sequence(&self, product: &str, seq: Sequence) -> Option<&Checkpoint> {
+        self.checkpoints
+            .iter()
+            .take_while(|c| c.sequences.get(product).map_or(true, |&s| s < seq))
+            .last()
+    }
+
+    /// Given `r`, the chunk this is the index of, returns its decompressed
+    /// contents from `checkpoint` on.
+    pub fn open_at<R: Read + 'static>(
+        &self,
+        mut r: R,
+        checkpoint: &Checkpoint,
+    ) -> io::Result<Box<Read>> {
+        skip(&mut r, checkpoint.restart.compressed_offset)?;
+        let mut d = compression::decoder(self.compression, r)?;
+        skip(&mut d, checkpoint.offset - checkpoint.restart.offset)?;
+        Ok(d)
+    }
+}
+
+/// Counts the bytes consumed from a `BufRead`. Decoders of a single gzip
+/// member or zstd frame consume exactly its bytes, so this tells where the
+/// next one starts.
+struct Counting<B> {
+    inner: B,
+    pos: u64,
+}
+
+impl<B: BufRead> Read for Counting<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<B: BufRead> BufRead for Counting<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.pos += amt as u64;
+    }
+}
+
+/// The fields of a message needed for checkpoints.
+#[derive(Deserialize)]
+struct Header {
+    time: Option<String>,
+    product_id: Option<String>,
+    sequence: Option<u64>,
+}
+
+/// Splits decompressed data into lines, taking checkpoints along the way.
+struct Builder {
+    interval: u64,
+    uncompressed: bool,
+    checkpoints: Vec<Checkpoint>,
+    sequences: BTreeMap<String, Sequence>,
+    /// Decompressed bytes seen so far.
+    offset: u64,
+    /// The restart point of the member or frame being decoded.
+    restart: RestartPoint,
+    line: Vec<u8>,
+    line_offset: u64,
+    line_restart: RestartPoint,
+    /// Lines ended so far.
+    lines: u64,
+}
+
+impl Builder {
+    fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.line.is_empty() {
+                self.line_offset = self.offset;
+                self.line_restart = if self.uncompressed {
+                    RestartPoint {
+                        compressed_offset: self.offset,
+                        offset: self.offset,
+                    }
+                } else {
+                    self.restart
+                };
+            }
+            match data.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    self.line.extend_from_slice(&data[..i]);
+                    self.offset += i as u64 + 1;
+                    data = &data[i + 1..];
+                    self.end_line();
+                }
+                None => {
+                    self.line.extend_from_slice(data);
+                    self.offset += data.len() as u64;
+                    data = &[];
+                }
+            }
+        }
+    }
+
+    /// Takes a checkpoint at the line if one is due, then records its
+    /// sequence number. Lines which aren't messages are skipped.
+    fn end_line(&mut self) {
+        if let Ok(header) = serde_json::from_slice::<Header>(&self.line) {
+            let time = header.time.and_then(|t| Timestamp::parse(&t).ok());
+            let due = match self.checkpoints.last() {
+                Some(last) => self.line_offset - last.offset >= self.interval,
+                None => true,
+            };
+            if let (Some(time), true) = (time, due) {
+                self.checkpoints.push(Checkpoint {
+                    offset: self.line_offset,
+                    line: self.lines + 1,
+                    restart: self.line_restart,
+                    time,
+                    sequences: self.sequences.clone(),
+                });
+            }
+            if let (Some(product), Some(seq)) = (header.product_id, header.sequence) {
+                self.sequences.insert(product, Sequence::from(seq));
+            }
+        }
+        self.line.clear();
+        self.lines += 1;
+    }
+
+    fn copy_from<R: Read>(&mut self, mut r: R) -> io::Result<()> {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = match r.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.feed(&buf[..n]);
+        }
+    }
+}
+
+/// Builds the index of the chunk at `path`, whose contents are `r`, with
+/// checkpoints about every `interval` bytes of decompressed data.
+pub fn build<P: AsRef<Path>, R: Read>(path: P, r: R, interval: u64) -> io::Result<ChunkIndex> {
+    let mut r = Counting {
+        inner: BufReader::new(r),
+        pos: 0,
+    };
+    let compression = compression::detect(path, &mut r)?;
+    let start = RestartPoint {
+        compressed_offset: 0,
+        offset: 0,
+    };
+    let mut builder = Builder {
+        interval,
+        uncompressed: compression == Compression::None,
+        checkpoints: Vec::new(),
+        sequences: BTreeMap::new(),
+        offset: 0,
+        restart: start,
+        line: Vec::new(),
+        line_offset: 0,
+        line_restart: start,
+        lines: 0,
+    };
+
+    // Decode one gzip member or zstd frame at a time, so that the start of
+    // each is known.
+    loop {
+        builder.restart = RestartPoint {
+            compressed_offset: r.pos,
+            offset: builder.offset,
+        };
+        if r.fill_buf()?.is_empty() {
+            break;
+        }
+        match compression {
+            Compression::None => builder.copy_from(&mut r)?,
+            Compression::Gzip => builder.copy_from(GzDecoder::new(&mut r))?,
+            Compression::Zstd => {
+                let d = zstd::stream::read::Decoder::with_buffer(&mut r)?.single_frame();
+                builder.copy_from(d)?;
+            }
+        }
+    }
+    if !builder.line.is_empty() {
+        builder.end_line();
+    }
+
+    Ok(ChunkIndex {
+        version: VERSION,
+        compression,
+        chunk_size: r.pos,
+        checkpoints: builder.checkpoints,
+    })
+}
+
+/// Writes `r`, the decompressed contents of a chunk, to `w` as a series of
+/// gzip members or zstd frames of about `member_size` bytes each, split at
+/// line ends. They decompress to the same contents as a single one, but
+/// give an index somewhere to restart decompression every `member_size`
+/// bytes.
+pub fn recompress<R: Read, W: Write>(
+    compression: Compression,
+    r: R,
+    mut w: W,
+    member_size: u64,
+) -> io::Result<()> {
+    let mut r = BufReader::new(r);
+    let mut member = Vec::new();
+    loop {
+        let n = r.read_until(b'\n', &mut member)?;
+        if n == 0 || member.len() as u64 >= member_size {
+            if !member.is_empty() {
+                w.write_all(&compression::compress(compression, &member)?)?;
+                member.clear();
+            }
+            if n == 0 {
+                return w.flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use flate2;
+    use flate2::write::GzEncoder;
+
+    fn line(seq: u64, product: &str, secs: u64) -> String {
+        format!(
+            r#"{{"type":"heartbeat","sequence":{},"product_id":"{}","time":"2018-02-25T17:00:{:02}.000000Z"}}"#,
+            seq, product, secs
+        ) + "\n"
+    }
+
+    /// Three "members" of two lines each.
+    fn members() -> Vec<String> {
+        vec![
+            line(10, "BTC-USD", 0) + &line(5, "ETH-USD", 1),
+            line(11, "BTC-USD", 2) + &line(12, "BTC-USD", 3),
+            line(6, "ETH-USD", 4) + &line(13, "BTC-USD", 5),
+        ]
+    }
+
+    fn gzip(s: &str) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        e.write_all(s.as_bytes()).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn zstd(s: &str) -> Vec<u8> {
+        zstd::stream::encode_all(s.as_bytes(), 0).unwrap()
+    }
+
+    fn compress(path: &str, f: fn(&str) -> Vec<u8>) -> (ChunkIndex, Vec<u8>) {
+        let data: Vec<u8> = members().iter().flat_map(|m| f(m)).collect();
+        (build(path, &data[..], 0).unwrap(), data)
+    }
+
+    fn read_from(index: &ChunkIndex, data: &[u8], checkpoint: &Checkpoint) -> String {
+        let mut s = String::new();
+        index
+            .open_at(Cursor::new(data.to_vec()), checkpoint)
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        s
+    }
+
+    fn ts(secs: u64) -> Timestamp {
+        Timestamp::parse(&format!("2018-02-25T17:00:{:02}Z", secs)).unwrap()
+    }
+
+    #[test]
+    fn restart_points() {
+        let all = members().concat();
+        for &(path, f) in &[
+            ("ws.txt", (|s: &str| s.as_bytes().to_vec()) as fn(&str) -> Vec<u8>),
+            ("ws.txt.gz", gzip),
+            ("ws.txt.zst", zstd),
+        ] {
+            let (index, data) = compress(path, f);
+            assert_eq!(6, index.checkpoints.len(), "{}", path);
+
+            // The 4th line is in the middle of the second member.
+            let c = &index.checkpoints[3];
+            let second = members()[0].len() as u64;
+            assert_eq!(second + line(11, "BTC-USD", 2).len() as u64, c.offset);
+            assert_eq!(4, c.line);
+            if index.compression == Compression::None {
+                assert_eq!(c.offset, c.restart.offset);
+            } else {
+                assert_eq!(second, c.restart.offset, "{}", path);
+                assert_eq!(f(&members()[0]).len() as u64, c.restart.compressed_offset);
+            }
+            assert_eq!(ts(3), c.time);
+            assert_eq!(Some(&Sequence::from(11)), c.sequences.get("BTC-USD"));
+            assert_eq!(Some(&Sequence::from(5)), c.sequences.get("ETH-USD"));
+
+            for c in &index.checkpoints {
+                assert_eq!(&all[c.offset as usize..], read_from(&index, &data, c), "{}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn seek() {
+        let (index, data) = compress("ws.txt.gz", gzip);
+        assert_eq!(None, index.seek_time(ts(0)));
+        assert_eq!(ts(2), index.seek_time(ts(3)).unwrap().time);
+        assert_eq!(ts(5), index.seek_time(ts(59)).unwrap().time);
+
+        let c = index.seek_sequence("BTC-USD", Sequence::from(12)).unwrap();
+        assert!(read_from(&index, &data, c).starts_with(&line(12, "BTC-USD", 3)));
+        let c = index.seek_sequence("ETH-USD", Sequence::from(6)).unwrap();
+        assert!(read_from(&index, &data, c).starts_with(&line(6, "ETH-USD", 4)));
+        let c = index.seek_sequence("BTC-USD", Sequence::from(10)).unwrap();
+        assert_eq!(0, c.offset);
+    }
+
+    #[test]
+    fn recompressed_members() {
+        let data = members().concat();
+        let member_size = members()[0].len() as u64;
+        for &(path, compression) in &[("ws.txt.gz", Compression::Gzip), ("ws.txt.zst", Compression::Zstd)] {
+            let single = compression::compress(compression, data.as_bytes()).unwrap();
+            let single_index = build(path, &single[..], 0).unwrap();
+            assert!(single_index.checkpoints.iter().all(|c| c.restart.offset == 0));
+            assert_eq!(single.len() as u64, single_index.chunk_size);
+
+            let mut split = Vec::new();
+            let decompressed = compression::decoder(compression, Cursor::new(single)).unwrap();
+            recompress(compression, decompressed, &mut split, member_size).unwrap();
+            let index = build(path, &split[..], 0).unwrap();
+            let restarts: Vec<u64> = index.checkpoints.iter().map(|c| c.restart.offset).collect();
+            let (first, second) = (members()[0].len() as u64, members()[1].len() as u64);
+            assert_eq!(vec![0, 0, first, first, first + second, first + second], restarts);
+            for c in &index.checkpoints {
+                assert_eq!(&data[c.offset as usize..], read_from(&index, &split, c), "{}", path);
+            }
+            assert!(index.is_stale(single_index.chunk_size));
+            assert!(!index.is_stale(split.len() as u64));
+        }
+    }
+
+    #[test]
+    fn interval_and_round_trip() {
+        let data = members().concat();
+        let interval = line(10, "BTC-USD", 0).len() as u64 * 3;
+        let index = build("ws.txt", data.as_bytes(), interval).unwrap();
+        let offsets: Vec<u64> = index.checkpoints.iter().map(|c| c.offset).collect();
+        let second = (members()[0].len() + members()[1].len()) as u64;
+        assert_eq!(vec![0, second], offsets);
+
+        let mut json = Vec::new();
+        index.write(&mut json).unwrap();
+        assert_eq!(index, ChunkIndex::read(&json[..]).unwrap());
+    }
+}
@@ -3,10 +3,29 @@ extern crate flate2;
 extern crate futures;
 extern crate glob;
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate tar;
 extern crate tokio;
+extern crate zstd;
 
 pub mod book;
+pub mod candles;
+pub mod catalog;
+pub mod coinbase;
+pub mod compression;
+pub mod decode;
+pub mod eventlog;
 pub mod historical;
+pub mod index;
+pub mod sequence;
+pub mod tape;
 
 mod price;
 pub type Price = price::Price;
+
+mod timestamp;
+pub type Timestamp = timestamp::Timestamp;
+pub type ParseTimestampError = timestamp::ParseTimestampError;
@@ -47,12 +47,23 @@ impl Price {
         */
         Ok(Self::from(s.parse::<f64>()?))
     }
+
+    /// The fixed-point value, in units of 10^-8.
+    pub fn to_raw(self) -> i64 {
+        self.val
+    }
+
+    pub fn from_raw(val: i64) -> Price {
+        Price { val }
+    }
 }
 
 impl From<f64> for Price {
+    /// Rounds to the nearest representable price. Truncating would turn e.g.
+    /// 0.29 into 0.28999999, since it can't be represented exactly as an f64.
     fn from(px: f64) -> Self {
         Price {
-            val: (px * WHOLE as f64) as i64,
+            val: (px * WHOLE as f64).round() as i64,
         }
     }
 }
@@ -192,4 +203,13 @@ mod tests {
         assert_eq!(Price::from(11.), Price::from(110.) / 10);
         assert_eq!(Price::from(25.), Price::from(10.) + Price::from(15.));
     }
+
+    #[test]
+    fn parse_round_trip() {
+        for s in &["0.29", "0.28999999", "1024.01512", "-0.10", "6543.21"] {
+            let px = Price::parse(s).unwrap();
+            assert_eq!(*s, px.to_string());
+            assert_eq!(px, Price::parse(&px.to_string()).unwrap());
+        }
+    }
 }
@@ -0,0 +1,268 @@
+/// Sequence numbers, and tracking of their continuity within a feed.
+
+use std::fmt;
+use std::fmt::Display;
+use std::ops;
+
+/// The sequence number of the source event.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Sequence(u64);
+
+impl Sequence {
+    pub fn next(self) -> Sequence {
+        Sequence(self.0 + 1)
+    }
+
+    /// Whether `other` immediately follows this sequence number, i.e. there is
+    /// nothing missing between the two.
+    pub fn is_followed_by(self, other: Sequence) -> bool {
+        self.next() == other
+    }
+
+    /// The number of sequence numbers strictly between this one and `later`.
+    /// Zero if `later` is not after this one.
+    pub fn gap(self, later: Sequence) -> u64 {
+        if later.0 > self.0 {
+            later.0 - self.0 - 1
+        } else {
+            0
+        }
+    }
+}
+
+impl From<u64> for Sequence {
+    fn from(s: u64) -> Self {
+        Sequence(s)
+    }
+}
+
+impl From<Sequence> for u64 {
+    fn from(s: Sequence) -> Self {
+        s.0
+    }
+}
+
+/// The signed distance between two sequence numbers.
+impl ops::Sub for Sequence {
+    type Output = i64;
+    fn sub(self, rhs: Self) -> i64 {
+        self.0 as i64 - rhs.0 as i64
+    }
+}
+
+impl Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SequenceEvent {
+    /// The first sequence number seen.
+    First,
+    /// The sequence number immediately follows the last one.
+    Contiguous,
+    /// The stream did not continue from the last sequence number (the first
+    /// field) to the new one (the second).
+    Skipped(Sequence, Sequence),
+    /// The sequence number was seen already.
+    Duplicate(Sequence),
+    /// The sequence number (the second field) is before the last one (the
+    /// first), and can't be told apart from a duplicate.
+    OutOfOrder(Sequence, Sequence),
+    /// The sequence number is before the last one, but fills a gap skipped
+    /// within the reorder window.
+    Reordered(Sequence),
+}
+
+/// How many of each kind of discontinuity a tracker has seen.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SequenceCounts {
+    pub messages: u64,
+    pub skipped: u64,
+    /// Sequence numbers skipped and not filled in since.
+    pub missing: u64,
+    pub duplicate: u64,
+    pub out_of_order: u64,
+    pub reordered: u64,
+}
+
+/// Tracks the sequence numbers of a single stream (e.g. one product) and
+/// reports any discontinuities.
+///
+/// The last sequence number never goes backward. With a reorder window, the
+/// tracker remembers which sequence numbers were skipped within that distance
+/// of the last one, so it can tell messages arriving late from duplicates.
+#[derive(Clone, Debug)]
+pub struct SequenceTracker {
+    first: Option<Sequence>,
+    last: Option<Sequence>,
+    window: u64,
+    /// Inclusive ranges of skipped sequence numbers within the window.
+    missing: Vec<(Sequence, Sequence)>,
+    counts: SequenceCounts,
+}
+
+impl SequenceTracker {
+    pub fn new() -> SequenceTracker {
+        SequenceTracker::with_reorder_window(0)
+    }
+
+    /// A tracker which recognises messages up to `window` sequence numbers
+    /// before the last one as filling earlier gaps.
+    pub fn with_reorder_window(window: u64) -> SequenceTracker {
+        SequenceTracker {
+            first: None,
+            last: None,
+            window,
+            missing: Vec::new(),
+            counts: SequenceCounts::default(),
+        }
+    }
+
+    pub fn update(&mut self, seq: Sequence) -> SequenceEvent {
+        self.counts.messages += 1;
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.first = Some(seq);
+                self.last = Some(seq);
+                return SequenceEvent::First;
+            }
+        };
+
+        if seq > last {
+            self.last = Some(seq);
+            let event = if last.is_followed_by(seq) {
+                SequenceEvent::Contiguous
+            } else {
+                self.counts.skipped += 1;
+                self.counts.missing += last.gap(seq);
+                if self.window > 0 {
+                    self.missing.push((last.next(), Sequence(seq.0 - 1)));
+                }
+                SequenceEvent::Skipped(last, seq)
+            };
+            let window = self.window;
+            self.missing.retain(|&(_, to)| seq.0 - to.0 <= window);
+            return event;
+        }
+        if seq == last {
+            self.counts.duplicate += 1;
+            return SequenceEvent::Duplicate(seq);
+        }
+
+        let in_window = last.0 - seq.0 <= self.window && self.first.map_or(false, |f| f <= seq);
+        if !in_window {
+            self.counts.out_of_order += 1;
+            return SequenceEvent::OutOfOrder(last, seq);
+        }
+        match self.missing.iter().position(|&(from, to)| from <= seq && seq <= to) {
+            Some(i) => {
+                let (from, to) = self.missing.remove(i);
+                if from < seq {
+                    self.missing.push((from, Sequence(seq.0 - 1)));
+                }
+                if seq < to {
+                    self.missing.push((seq.next(), to));
+                }
+                self.counts.missing -= 1;
+                self.counts.reordered += 1;
+                SequenceEvent::Reordered(seq)
+            }
+            None => {
+                self.counts.duplicate += 1;
+                SequenceEvent::Duplicate(seq)
+            }
+        }
+    }
+
+    /// The first sequence number seen, if any.
+    pub fn first(&self) -> Option<Sequence> {
+        self.first
+    }
+
+    /// The highest sequence number seen, if any.
+    pub fn last(&self) -> Option<Sequence> {
+        self.last
+    }
+
+    pub fn counts(&self) -> SequenceCounts {
+        self.counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seq(s: u64) -> Sequence {
+        Sequence::from(s)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(seq(11), seq(10).next());
+        assert!(seq(10).is_followed_by(seq(11)));
+        assert!(!seq(10).is_followed_by(seq(12)));
+        assert!(!seq(10).is_followed_by(seq(10)));
+        assert_eq!(0, seq(10).gap(seq(11)));
+        assert_eq!(4, seq(10).gap(seq(15)));
+        assert_eq!(0, seq(10).gap(seq(9)));
+        assert_eq!(-5, seq(10) - seq(15));
+        assert!(seq(9) < seq(10));
+        assert_eq!("12345", format!("{}", seq(12345)));
+    }
+
+    #[test]
+    fn tracker() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(None, tracker.first());
+        assert_eq!(SequenceEvent::First, tracker.update(seq(5)));
+        assert_eq!(SequenceEvent::Contiguous, tracker.update(seq(6)));
+        assert_eq!(SequenceEvent::Skipped(seq(6), seq(9)), tracker.update(seq(9)));
+        assert_eq!(SequenceEvent::Contiguous, tracker.update(seq(10)));
+        assert_eq!(Some(seq(5)), tracker.first());
+        assert_eq!(Some(seq(10)), tracker.last());
+    }
+
+    #[test]
+    fn duplicates_and_reordering() {
+        let mut tracker = SequenceTracker::new();
+        tracker.update(seq(5));
+        assert_eq!(SequenceEvent::Skipped(seq(5), seq(8)), tracker.update(seq(8)));
+        assert_eq!(SequenceEvent::Duplicate(seq(8)), tracker.update(seq(8)));
+        assert_eq!(SequenceEvent::OutOfOrder(seq(8), seq(6)), tracker.update(seq(6)));
+        assert_eq!(Some(seq(8)), tracker.last());
+        assert_eq!(SequenceEvent::Contiguous, tracker.update(seq(9)));
+        assert_eq!(
+            SequenceCounts {
+                messages: 5,
+                skipped: 1,
+                missing: 2,
+                duplicate: 1,
+                out_of_order: 1,
+                reordered: 0,
+            },
+            tracker.counts()
+        );
+
+        let mut tracker = SequenceTracker::with_reorder_window(5);
+        tracker.update(seq(5));
+        tracker.update(seq(9));
+        assert_eq!(SequenceEvent::Reordered(seq(7)), tracker.update(seq(7)));
+        assert_eq!(SequenceEvent::Duplicate(seq(7)), tracker.update(seq(7)));
+        assert_eq!(SequenceEvent::Duplicate(seq(5)), tracker.update(seq(5)));
+        assert_eq!(SequenceEvent::OutOfOrder(seq(9), seq(4)), tracker.update(seq(4)));
+        assert_eq!(SequenceEvent::Contiguous, tracker.update(seq(10)));
+        assert_eq!(SequenceEvent::Reordered(seq(6)), tracker.update(seq(6)));
+        // 8 falls out of the window once 14 is seen.
+        tracker.update(seq(14));
+        assert_eq!(SequenceEvent::OutOfOrder(seq(14), seq(8)), tracker.update(seq(8)));
+        assert_eq!(SequenceEvent::Reordered(seq(12)), tracker.update(seq(12)));
+
+        let counts = tracker.counts();
+        assert_eq!((2, 3, 3), (counts.skipped, counts.missing, counts.reordered));
+        assert_eq!((2, 2), (counts.duplicate, counts.out_of_order));
+    }
+}
@@ -0,0 +1,247 @@
+/// A record of trades, built from the match events of a level 3 feed.
+
+use std::cmp::Ordering;
+use std::io;
+use std::io::Write;
+
+use serde_json;
+
+use book::{ChangeEvent, DoneEvent, Level3FeedListener, MatchEvent, NewOrderEvent, OpenEvent,
+           Sequence, Side, Timestamp};
+use price::Price;
+
+#[derive(Clone, Debug)]
+pub struct Trade {
+    seq: Sequence,
+    time: Timestamp,
+    maker_order_id: String,
+    taker_order_id: String,
+    aggressor: Side,
+    price: Price,
+    size: Price,
+}
+
+impl Trade {
+    pub fn seq(&self) -> Sequence {
+        self.seq
+    }
+
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    pub fn maker_order_id(&self) -> &str {
+        &self.maker_order_id
+    }
+
+    pub fn taker_order_id(&self) -> &str {
+        &self.taker_order_id
+    }
+
+    /// The side of the taker order, i.e. the opposite of the resting order's side.
+    pub fn aggressor(&self) -> Side {
+        self.aggressor
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn size(&self) -> Price {
+        self.size
+    }
+}
+
+impl<'a, 'b> From<&'b MatchEvent<'a>> for Trade {
+    fn from(event: &MatchEvent<'a>) -> Self {
+        Trade {
+            seq: event.seq(),
+            time: event.time(),
+            maker_order_id: event.maker_order_id().to_owned(),
+            taker_order_id: event.taker_order_id().to_owned(),
+            aggressor: event.side().opposite(),
+            price: event.price(),
+            size: event.size(),
+        }
+    }
+}
+
+/// Records every match seen on the feed, in feed order.
+pub struct TradeTape {
+    trades: Vec<Trade>,
+}
+
+impl TradeTape {
+    pub fn new() -> TradeTape {
+        TradeTape { trades: Vec::new() }
+    }
+
+    pub fn trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    /// Returns the trades with `start <= time < end`.
+    ///
+    /// Relies on the feed delivering matches in time order.
+    pub fn between(&self, start: Timestamp, end: Timestamp) -> &[Trade] {
+        let lower = lower_bound(&self.trades, start);
+        let upper = lower_bound(&self.trades, end);
+        if upper <= lower {
+            return &[];
+        }
+        &self.trades[lower..upper]
+    }
+}
+
+/// Index of the first trade with a time at or after `time`.
+fn lower_bound(trades: &[Trade], time: Timestamp) -> usize {
+    trades
+        .binary_search_by(|t| {
+            if t.time < time {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        })
+        .unwrap_err()
+}
+
+impl Level3FeedListener for TradeTape {
+    fn on_add<'a>(&mut self, _event: &NewOrderEvent<'a>) {}
+    fn on_open<'a>(&mut self, _event: &OpenEvent<'a>) {}
+
+    fn on_match<'a>(&mut self, event: &MatchEvent<'a>) {
+        self.trades.push(Trade::from(event));
+    }
+
+    fn on_change<'a>(&mut self, _event: &ChangeEvent<'a>) {}
+    fn on_done<'a>(&mut self, _event: &DoneEvent<'a>) {}
+}
+
+fn side_str(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "buy",
+        Side::Ask => "sell",
+    }
+}
+
+/// Writes trades as CSV, with a header row.
+pub fn write_csv<W: Write>(trades: &[Trade], mut w: W) -> io::Result<()> {
+    writeln!(w, "time,sequence,maker_order_id,taker_order_id,aggressor,price,size")?;
+    for t in trades {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{}",
+            t.time,
+            t.seq,
+            t.maker_order_id,
+            t.taker_order_id,
+            side_str(t.aggressor),
+            t.price,
+            t.size
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes trades as JSON, one object per line. Prices and sizes are written as
+/// strings, as in the Coinbase feed.
+pub fn write_json_lines<W: Write>(trades: &[Trade], mut w: W) -> io::Result<()> {
+    for t in trades {
+        let obj = json!({
+            "time": t.time.to_string(),
+            "sequence": u64::from(t.seq),
+            "maker_order_id": t.maker_order_id,
+            "taker_order_id": t.taker_order_id,
+            "aggressor": side_str(t.aggressor),
+            "price": t.price.to_string(),
+            "size": t.size.to_string(),
+        });
+        serde_json::to_writer(&mut w, &obj)?;
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(p: f64) -> Price {
+        Price::from(p)
+    }
+
+    fn match_event<'a>(secs: u64, maker: &'a str, taker: &'a str, side: Side) -> MatchEvent<'a> {
+        MatchEvent::new(
+            Sequence::from(secs),
+            ts(secs),
+            maker,
+            taker,
+            side,
+            px(10.),
+            px(1.5),
+        )
+    }
+
+    fn ts(secs: u64) -> Timestamp {
+        let start = Timestamp::parse("2018-02-25T17:00:00Z").unwrap();
+        Timestamp::from_nanos(start.nanos() + secs * 1_000_000_000)
+    }
+
+    fn tape() -> TradeTape {
+        let mut tape = TradeTape::new();
+        tape.on_match(&match_event(10, "m1", "t1", Side::Bid));
+        tape.on_match(&match_event(20, "m2", "t2", Side::Ask));
+        tape.on_match(&match_event(20, "m3", "t3", Side::Ask));
+        tape.on_match(&match_event(30, "m4", "t4", Side::Bid));
+        tape
+    }
+
+    #[test]
+    fn aggressor_side() {
+        let tape = tape();
+        assert_eq!(Side::Ask, tape.trades()[0].aggressor());
+        assert_eq!(Side::Bid, tape.trades()[1].aggressor());
+    }
+
+    #[test]
+    fn between() {
+        let tape = tape();
+        let ids = |trades: &[Trade]| -> Vec<String> {
+            trades.iter().map(|t| t.maker_order_id().to_owned()).collect()
+        };
+        assert_eq!(
+            vec!["m2", "m3"],
+            ids(tape.between(ts(20), ts(30)))
+        );
+        assert_eq!(
+            vec!["m1", "m2", "m3", "m4"],
+            ids(tape.between(ts(0), ts(31)))
+        );
+        assert!(tape.between(ts(21), ts(30)).is_empty());
+        assert!(tape.between(ts(30), ts(10)).is_empty());
+    }
+
+    #[test]
+    fn export() {
+        let tape = tape();
+        let mut csv = Vec::new();
+        write_csv(&tape.trades()[..1], &mut csv).unwrap();
+        assert_eq!(
+            "time,sequence,maker_order_id,taker_order_id,aggressor,price,size\n\
+             2018-02-25T17:00:10.000000Z,10,m1,t1,sell,10.00,1.50\n",
+            String::from_utf8(csv).unwrap()
+        );
+
+        let mut json = Vec::new();
+        write_json_lines(&tape.trades()[..2], &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let lines: Vec<serde_json::Value> = json.lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("2018-02-25T17:00:20.000000Z", lines[1]["time"]);
+        assert_eq!("buy", lines[1]["aggressor"]);
+        assert_eq!("10.00", lines[1]["price"]);
+    }
+}
@@ -0,0 +1,253 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A point in time, in nanoseconds since the Unix epoch.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Timestamp(u64);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParseTimestampError;
+
+impl Display for ParseTimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid timestamp; expected YYYY-MM-DDTHH:MM:SS[.fraction]Z")
+    }
+}
+
+impl Error for ParseTimestampError {}
+
+impl Timestamp {
+    pub fn from_nanos(nanos: u64) -> Timestamp {
+        Timestamp(nanos)
+    }
+
+    pub fn nanos(&self) -> u64 {
+        self.0
+    }
+
+    /// Parses a UTC timestamp in the format used by Coinbase, e.g.
+    /// `2018-02-25T17:00:00.123456Z`. The fractional part is optional and may
+    /// have up to nine digits.
+    ///
+    /// This is much faster than going through chrono, which matters when
+    /// parsing every message of a feed.
+    pub fn parse(s: &str) -> Result<Timestamp, ParseTimestampError> {
+        let b = s.as_bytes();
+        if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[10] != b'T' || b[13] != b':'
+            || b[16] != b':' || b[b.len() - 1] != b'Z'
+        {
+            return Err(ParseTimestampError);
+        }
+
+        let year = parse_digits(&b[0..4])?;
+        let month = parse_digits(&b[5..7])?;
+        let day = parse_digits(&b[8..10])?;
+        let hour = parse_digits(&b[11..13])?;
+        let minute = parse_digits(&b[14..16])?;
+        let second = parse_digits(&b[17..19])?;
+        if year < 1970 || month < 1 || month > 12 || day < 1
+            || day > days_in_month(year, month) || hour > 23 || minute > 59
+            || second > 59
+        {
+            return Err(ParseTimestampError);
+        }
+
+        let frac = &b[19..b.len() - 1];
+        let mut nanos = 0;
+        if !frac.is_empty() {
+            let digits = &frac[1..];
+            if frac[0] != b'.' || digits.is_empty() || digits.len() > 9 {
+                return Err(ParseTimestampError);
+            }
+            nanos = parse_digits(digits)? * 10u64.pow(9 - digits.len() as u32);
+        }
+
+        // Years from 2555 on don't fit in a u64 of nanoseconds.
+        let secs = days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60
+            + second;
+        secs.checked_mul(NANOS_PER_SEC)
+            .and_then(|secs| secs.checked_add(nanos))
+            .map(Timestamp)
+            .ok_or(ParseTimestampError)
+    }
+
+    /// Converts a chrono time, unless it's before the Unix epoch or too far
+    /// after it to fit.
+    pub fn from_datetime(dt: DateTime<Utc>) -> Option<Timestamp> {
+        if dt.timestamp() < 0 {
+            return None;
+        }
+        (dt.timestamp() as u64)
+            .checked_mul(NANOS_PER_SEC)
+            .and_then(|secs| secs.checked_add(u64::from(dt.timestamp_subsec_nanos())))
+            .map(Timestamp)
+    }
+}
+
+fn parse_digits(b: &[u8]) -> Result<u64, ParseTimestampError> {
+    let mut val = 0;
+    for &c in b {
+        if c < b'0' || c > b'9' {
+            return Err(ParseTimestampError);
+        }
+        val = val * 10 + (c - b'0') as u64;
+    }
+    Ok(val)
+}
+
+fn is_leap_year(year: u64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date on or after the epoch.
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Clamps times before the epoch to the epoch, and times too late to fit to
+/// the latest timestamp. Use `Timestamp::from_datetime` to reject them.
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(dt: DateTime<Utc>) -> Self {
+        match Timestamp::from_datetime(dt) {
+            Some(t) => t,
+            None if dt.timestamp() < 0 => Timestamp(0),
+            None => Timestamp(u64::max_value()),
+        }
+    }
+}
+
+impl From<Timestamp> for DateTime<Utc> {
+    fn from(t: Timestamp) -> Self {
+        Utc.timestamp(
+            (t.0 / NANOS_PER_SEC) as i64,
+            (t.0 % NANOS_PER_SEC) as u32,
+        )
+    }
+}
+
+impl Display for Timestamp {
+    /// Formats the same way as Coinbase, with microsecond precision, unless
+    /// the timestamp has a finer precision than that.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dt = DateTime::<Utc>::from(*self);
+        if self.0 % 1000 == 0 {
+            write!(f, "{}", dt.format("%Y-%m-%dT%H:%M:%S%.6fZ"))
+        } else {
+            write!(f, "{}", dt.format("%Y-%m-%dT%H:%M:%S%.9fZ"))
+        }
+    }
+}
+
+/// Serialized in the same format as it's displayed.
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Timestamp::parse(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chrono_parse(s: &str) -> Timestamp {
+        Timestamp::from(s.parse::<DateTime<Utc>>().unwrap())
+    }
+
+    #[test]
+    fn parse() {
+        for s in &[
+            "1970-01-01T00:00:00Z",
+            "2018-02-25T17:00:00.123456Z",
+            "2018-02-28T23:59:59.999999Z",
+            "2016-02-29T12:34:56.1Z",
+            "2000-03-01T00:00:00.000000001Z",
+            "2100-12-31T23:59:59Z",
+        ] {
+            assert_eq!(chrono_parse(s), Timestamp::parse(s).unwrap(), "{}", s);
+        }
+        assert_eq!(
+            Timestamp::from_nanos(1519578000123456000),
+            Timestamp::parse("2018-02-25T17:00:00.123456Z").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in &[
+            "",
+            "2018-02-25T17:00:00",
+            "2018-02-25 17:00:00Z",
+            "2018-02-25T17:00:00.Z",
+            "2018-02-25T17:00:00.1234567890Z",
+            "2018-02-25T17:00:00,123Z",
+            "2018-02-29T17:00:00Z",
+            "2018-13-01T17:00:00Z",
+            "2018-02-25T24:00:00Z",
+            "1969-12-31T23:59:59Z",
+            "2555-01-01T00:00:00Z",
+            "9999-12-31T23:59:59Z",
+            "2018-0a-25T17:00:00Z",
+        ] {
+            assert_eq!(Err(ParseTimestampError), Timestamp::parse(s), "{}", s);
+        }
+    }
+
+    #[test]
+    fn display() {
+        for s in &["2018-02-25T17:00:00.123456Z", "2018-02-25T17:00:00.000000001Z"] {
+            assert_eq!(*s, format!("{}", Timestamp::parse(s).unwrap()));
+        }
+        assert_eq!(
+            "2018-02-25T17:00:00.000000Z",
+            format!("{}", Timestamp::parse("2018-02-25T17:00:00Z").unwrap())
+        );
+    }
+
+    #[test]
+    fn chrono_round_trip() {
+        let t = Timestamp::parse("2018-02-25T17:00:00.123456789Z").unwrap();
+        assert_eq!(t, Timestamp::from(DateTime::<Utc>::from(t)));
+        assert!(t < Timestamp::parse("2018-02-25T17:00:00.12345679Z").unwrap());
+    }
+
+    #[test]
+    fn out_of_range() {
+        let before = Utc.ymd(1969, 12, 31).and_hms(23, 59, 59);
+        let after = Utc.ymd(2600, 1, 1).and_hms(0, 0, 0);
+        assert_eq!(None, Timestamp::from_datetime(before));
+        assert_eq!(None, Timestamp::from_datetime(after));
+        assert_eq!(Timestamp::from_nanos(0), Timestamp::from(before));
+        assert_eq!(Timestamp::from_nanos(u64::max_value()), Timestamp::from(after));
+        let last = Timestamp::parse("2554-07-21T23:34:33Z").unwrap();
+        assert_eq!(Some(last), Timestamp::from_datetime(DateTime::<Utc>::from(last)));
+    }
+}